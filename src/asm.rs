@@ -0,0 +1,669 @@
+//! Assembles source written with the mnemonics of the doc comments in `chip8.rs` into a ROM.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! SPEED equ 2                 ; constants
+//! start:                      ; labels, optionally followed by an instruction
+//!     LD V0, SPEED
+//!     LD I, sprite
+//!     DRW V0, V0, 3
+//!     JP start + 2            ; numbers and symbols can be added and subtracted
+//! sprite:
+//!     db 0x3C, 0b01000010, "A"
+//!     dw 0x1234
+//!     include "more.asm"      ; relative to the file it appears in
+//! ```
+//!
+//! The ROM is assembled to run from 0x200, or 0x600 for the ETI 660. Everything `disasm::disassemble_rom` prints assembles
+//! back into the ROM it came from.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::chip8::Computer;
+
+// includes nested deeper than this are assumed to include each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+// constants defined in terms of each other
+const MAX_SYMBOL_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` to run on `computer`. Includes are looked up relative to the working directory.
+pub fn assemble(source: &str, computer: Computer) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(computer.program_start());
+    assembler.read_source(source, "<source>", Path::new("."), 0)?;
+    assembler.finish()
+}
+
+/// Assembles the file at `path` to run on `computer`.
+pub fn assemble_file(path: &Path, computer: Computer) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new(computer.program_start());
+    assembler.read_file(path, None, 0)?;
+    assembler.finish()
+}
+
+enum Statement {
+    Instruction(String, Vec<String>),
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+struct Line {
+    file: String,
+    number: usize,
+    statement: Statement,
+}
+
+enum Symbol {
+    Label(usize),
+    Constant(String, String, usize), // the expression, and the file and line it is defined on
+}
+
+struct Assembler {
+    lines: Vec<Line>,
+    symbols: HashMap<String, Symbol>,
+    origin: usize,
+    addr: usize,
+}
+
+impl Assembler {
+    fn new(origin: usize) -> Assembler {
+        Assembler {
+            lines: Vec::new(),
+            symbols: HashMap::new(),
+            origin,
+            addr: origin,
+        }
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        included_from: Option<(&str, usize)>,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|e| {
+            let (file, line) = included_from.unwrap_or((&name, 0));
+            error(file, line, format!("Couldn't read {name}: {e}"))
+        })?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.read_source(&source, &name, dir, depth)
+    }
+
+    // first pass: splits up the statements and works out where each one goes
+    fn read_source(
+        &mut self,
+        source: &str,
+        file: &str,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (i, line) in source.lines().enumerate() {
+            let number = i + 1;
+            let err = |message: String| error(file, number, message);
+            let mut text = strip_comment(line).trim();
+
+            if let Some((label, rest)) = text.split_once(':') {
+                let label = label.trim();
+                if is_identifier(label) {
+                    self.define(label, Symbol::Label(self.addr)).map_err(err)?;
+                    text = rest.trim();
+                }
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = split_word(text);
+            // NAME equ value
+            let (second, value) = split_word(rest);
+            if second.eq_ignore_ascii_case("equ") {
+                if !is_identifier(word) {
+                    return Err(err(format!("{word} is not a valid constant name")));
+                }
+                let symbol = Symbol::Constant(value.to_string(), file.to_string(), number);
+                self.define(word, symbol).map_err(err)?;
+                continue;
+            }
+
+            let operands = split_operands(rest);
+            let statement = match word.to_lowercase().as_str() {
+                "include" => {
+                    if depth == MAX_INCLUDE_DEPTH {
+                        return Err(err("Includes nested too deep".to_string()));
+                    }
+                    let name = rest.trim().trim_matches('"');
+                    if name.is_empty() {
+                        return Err(err("include needs a file name".to_string()));
+                    }
+                    self.read_file(&dir.join(name), Some((file, number)), depth + 1)?;
+                    continue;
+                }
+                "db" => Statement::Bytes(operands),
+                "dw" => Statement::Words(operands),
+                _ => Statement::Instruction(word.to_uppercase(), operands),
+            };
+            let size = match &statement {
+                Statement::Bytes(values) => values.iter().map(|v| data_len(v)).sum(),
+                Statement::Words(values) => values.len() * 2,
+                Statement::Instruction(mnemonic, operands) => instruction_len(mnemonic, operands),
+            };
+            self.lines.push(Line {
+                file: file.to_string(),
+                number,
+                statement,
+            });
+            self.addr += size;
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        if is_reserved(name) {
+            return Err(format!("{name} is reserved and can't be used as a name"));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(format!("{name} is already defined"));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    // second pass: every symbol is known now
+    fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.addr - self.origin);
+        for line in &self.lines {
+            let err = |message: String| error(&line.file, line.number, message);
+            let eval = |text: &str| self.eval(text, 0).map_err(err);
+            match &line.statement {
+                Statement::Bytes(values) => {
+                    for value in values {
+                        match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                            Some(text) => rom.extend_from_slice(text.as_bytes()),
+                            None => rom.push(fit(eval(value)?, 0xFF).map_err(err)? as u8),
+                        }
+                    }
+                }
+                Statement::Words(values) => {
+                    for value in values {
+                        let word = fit(eval(value)?, 0xFFFF).map_err(err)? as u16;
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+                Statement::Instruction(mnemonic, operands) => {
+                    let opcode = self.encode(mnemonic, operands).map_err(err)?;
+                    rom.extend_from_slice(&opcode);
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn eval(&self, text: &str, depth: usize) -> Result<usize, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("Expected a value".to_string());
+        }
+        // terms joined with + and -, left to right
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut rest = text;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            let value = i64::try_from(self.term(term, depth)?).ok();
+            total = value
+                .and_then(|v| match sign {
+                    1 => total.checked_add(v),
+                    _ => total.checked_sub(v),
+                })
+                .ok_or_else(|| format!("{text} is too large"))?;
+            if end == rest.len() {
+                break;
+            }
+            sign = if rest[end..].starts_with('+') { 1 } else { -1 };
+            rest = &rest[end + 1..];
+        }
+        usize::try_from(total).map_err(|_| format!("{text} is negative"))
+    }
+
+    fn term(&self, term: &str, depth: usize) -> Result<usize, String> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        match self.symbols.get(term) {
+            Some(Symbol::Label(addr)) => Ok(*addr),
+            Some(Symbol::Constant(expr, file, line)) => {
+                if depth == MAX_SYMBOL_DEPTH {
+                    return Err(format!("{term} is defined in terms of itself"));
+                }
+                // only the constant the line used is worth pointing at
+                self.eval(expr, depth + 1).map_err(|e| match depth {
+                    0 => format!("{e} (in {term}, defined at {file}:{line})"),
+                    _ => e,
+                })
+            }
+            None if term.is_empty() => Err("Expected a value".to_string()),
+            None => Err(format!("Unknown symbol {term}")),
+        }
+    }
+
+    fn encode(&self, mnemonic: &str, operands: &[String]) -> Result<Vec<u8>, String> {
+        let ops: Vec<Operand> = operands.iter().map(|o| Operand::parse(o)).collect();
+        let value = |i: usize, max: usize| -> Result<u16, String> {
+            match ops.get(i) {
+                Some(Operand::Value(text)) => fit(self.eval(text, 0)?, max).map(|v| v as u16),
+                _ => Err(format!("Operand {} of {mnemonic} should be a value", i + 1)),
+            }
+        };
+        use Operand::*;
+        let opcode: u16 = match (mnemonic, ops.as_slice()) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCD", [_]) => 0x00C0 | value(0, 0xF)?,
+            ("SCU", [_]) => 0x00D0 | value(0, 0xF)?,
+            ("SCR", []) => 0x00FB,
+            ("SCL", []) => 0x00FC,
+            ("EXIT", []) => 0x00FD,
+            ("LOW", []) => 0x00FE,
+            ("HIGH", []) => 0x00FF,
+            ("SYS", [_]) => value(0, 0xFFF)?,
+            ("JP", [V(0), _]) => 0xB000 | value(1, 0xFFF)?,
+            ("JP", [_]) => 0x1000 | value(0, 0xFFF)?,
+            ("CALL", [_]) => 0x2000 | value(0, 0xFFF)?,
+            ("SE", [V(x), V(y)]) => 0x5000 | nxy(*x, *y),
+            ("SE", [V(x), _]) => 0x3000 | nx(*x) | value(1, 0xFF)?,
+            ("SNE", [V(x), V(y)]) => 0x9000 | nxy(*x, *y),
+            ("SNE", [V(x), _]) => 0x4000 | nx(*x) | value(1, 0xFF)?,
+            ("SAVE", [V(x), V(y)]) => 0x5002 | nxy(*x, *y),
+            ("LOAD", [V(x), V(y)]) => 0x5003 | nxy(*x, *y),
+            ("LD", [V(x), V(y)]) => 0x8000 | nxy(*x, *y),
+            ("LD", [I, Long(text)]) => {
+                let addr = fit(self.eval(text, 0)?, 0xFFFF)? as u16;
+                let [hi, lo] = addr.to_be_bytes();
+                return Ok(vec![0xF0, 0x00, hi, lo]);
+            }
+            ("LD", [I, _]) => 0xA000 | value(1, 0xFFF)?,
+            ("LD", [V(x), Dt]) => 0xF007 | nx(*x),
+            ("LD", [V(x), K]) => 0xF00A | nx(*x),
+            ("LD", [V(x), IndirectI]) => 0xF065 | nx(*x),
+            ("LD", [V(x), R]) => 0xF085 | nx(*x),
+            ("LD", [V(x), _]) => 0x6000 | nx(*x) | value(1, 0xFF)?,
+            ("LD", [Dt, V(x)]) => 0xF015 | nx(*x),
+            ("LD", [St, V(x)]) => 0xF018 | nx(*x),
+            ("LD", [F, V(x)]) => 0xF029 | nx(*x),
+            ("LD", [Hf, V(x)]) => 0xF030 | nx(*x),
+            ("LD", [B, V(x)]) => 0xF033 | nx(*x),
+            ("LD", [IndirectI, V(x)]) => 0xF055 | nx(*x),
+            ("LD", [R, V(x)]) => 0xF075 | nx(*x),
+            ("ADD", [V(x), V(y)]) => 0x8004 | nxy(*x, *y),
+            ("ADD", [I, V(x)]) => 0xF01E | nx(*x),
+            ("ADD", [V(x), _]) => 0x7000 | nx(*x) | value(1, 0xFF)?,
+            ("OR", [V(x), V(y)]) => 0x8001 | nxy(*x, *y),
+            ("AND", [V(x), V(y)]) => 0x8002 | nxy(*x, *y),
+            ("XOR", [V(x), V(y)]) => 0x8003 | nxy(*x, *y),
+            ("SUB", [V(x), V(y)]) => 0x8005 | nxy(*x, *y),
+            ("SUBN", [V(x), V(y)]) => 0x8007 | nxy(*x, *y),
+            // without Vy the register shifts itself, whichever way the shift quirk is set
+            ("SHR", [V(x)]) => 0x8006 | nxy(*x, *x),
+            ("SHR", [V(x), V(y)]) => 0x8006 | nxy(*x, *y),
+            ("SHL", [V(x)]) => 0x800E | nxy(*x, *x),
+            ("SHL", [V(x), V(y)]) => 0x800E | nxy(*x, *y),
+            ("RND", [V(x), _]) => 0xC000 | nx(*x) | value(1, 0xFF)?,
+            ("DRW", [V(x), V(y), _]) => 0xD000 | nxy(*x, *y) | value(2, 0xF)?,
+            ("SKP", [V(x)]) => 0xE09E | nx(*x),
+            ("SKNP", [V(x)]) => 0xE0A1 | nx(*x),
+            ("PLANE", [_]) => 0xF001 | value(0, 0xF)? << 8,
+            ("AUDIO", []) => 0xF002,
+            ("PITCH", [V(x)]) => 0xF03A | nx(*x),
+            _ if !MNEMONICS.contains(&mnemonic) => {
+                return Err(format!("Unknown instruction {mnemonic}"))
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid operands for {mnemonic}: {}",
+                    operands.join(", ")
+                ))
+            }
+        };
+        Ok(opcode.to_be_bytes().to_vec())
+    }
+}
+
+const MNEMONICS: [&str; 35] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH", "DB", "DW", "INCLUDE",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    // the 16 bit address of xo-chip's long load
+    Long(String),
+    Value(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Operand {
+        let upper = text.to_uppercase();
+        match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "R" => Operand::R,
+            _ => {
+                if let Some(x) = register(text) {
+                    Operand::V(x)
+                } else if upper.starts_with("LONG ") {
+                    Operand::Long(text[5..].trim().to_string())
+                } else {
+                    Operand::Value(text.to_string())
+                }
+            }
+        }
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let x = text.strip_prefix(['V', 'v'])?;
+    if x.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(x, 16).ok()
+}
+
+fn nx(x: u8) -> u16 {
+    (x as u16) << 8
+}
+
+fn nxy(x: u8, y: u8) -> u16 {
+    (x as u16) << 8 | (y as u16) << 4
+}
+
+fn fit(value: usize, max: usize) -> Result<usize, String> {
+    if value > max {
+        return Err(format!("0x{value:X} doesn't fit, the most is 0x{max:X}"));
+    }
+    Ok(value)
+}
+
+fn instruction_len(mnemonic: &str, operands: &[String]) -> usize {
+    let long = operands.len() == 2 && matches!(Operand::parse(&operands[1]), Operand::Long(_));
+    if mnemonic == "LD" && long {
+        4
+    } else {
+        2
+    }
+}
+
+fn data_len(value: &str) -> usize {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(text) => text.len(),
+        None => 1,
+    }
+}
+
+/// Accepts `0x` hex, `0b` binary and decimal.
+fn parse_number(text: &str) -> Option<usize> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        usize::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        usize::from_str_radix(bin, 2).ok()
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    register(name).is_some()
+        || MNEMONICS.contains(&upper.as_str())
+        || ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "EQU"].contains(&upper.as_str())
+}
+
+// everything after a ; that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+// commas inside strings don't separate operands
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => operands.push(std::mem::take(&mut current).trim().to_string()),
+            _ => current.push(c),
+        }
+    }
+    operands.push(current.trim().to_string());
+    operands
+}
+
+fn error(file: &str, line: usize, message: String) -> AsmError {
+    AsmError {
+        file: file.to_string(),
+        line,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_chip8(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble(source, Computer::Normal)
+    }
+
+    // the message of the error `source` fails with, and the line it points at
+    fn failure(source: &str) -> (usize, String) {
+        let e = assemble_chip8(source).expect_err("should fail");
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let source = "
+start:
+    JP end
+    CALL start
+end: LD I, start + 2
+";
+        assert_eq!(
+            assemble_chip8(source).unwrap(),
+            [0x12, 0x04, 0x22, 0x00, 0xA2, 0x02]
+        );
+    }
+
+    #[test]
+    fn labels_follow_the_load_address() {
+        let source = "here: JP here";
+        assert_eq!(assemble(source, Computer::Eti).unwrap(), [0x16, 0x00]);
+    }
+
+    #[test]
+    fn constants_and_data() {
+        let source = "
+SPEED equ 2
+FAST equ SPEED + 1
+    LD V0, FAST
+    db 0x3C, 0b0101, \"A;B\"  ; the ; in the string isn't a comment
+    dw 0x1234
+";
+        assert_eq!(
+            assemble_chip8(source).unwrap(),
+            [0x60, 0x03, 0x3C, 0x05, b'A', b';', b'B', 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn long_loads_take_four_bytes() {
+        let source = "
+    LD I, long data
+data: db 1
+";
+        assert_eq!(
+            assemble(source, Computer::XoChip).unwrap(),
+            [0xF0, 0x00, 0x02, 0x04, 0x01]
+        );
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        assert_eq!(
+            failure("CLS\nJMP 0x200"),
+            (2, "Unknown instruction JMP".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_operands() {
+        assert_eq!(
+            failure("DRW V0, 3"),
+            (1, "Invalid operands for DRW: V0, 3".to_string())
+        );
+    }
+
+    #[test]
+    fn immediates_out_of_range() {
+        let too_big = |source| failure(source).1;
+        assert_eq!(too_big("LD V0, 256"), "0x100 doesn't fit, the most is 0xFF");
+        assert_eq!(
+            too_big("JP 0x1000"),
+            "0x1000 doesn't fit, the most is 0xFFF"
+        );
+        assert_eq!(
+            too_big("DRW V0, V1, 16"),
+            "0x10 doesn't fit, the most is 0xF"
+        );
+        assert_eq!(failure("LD V0, 1 - 2").1, "1 - 2 is negative");
+        assert_eq!(
+            failure("LD V0, 0x7FFFFFFFFFFFFFFF + 0x7FFFFFFFFFFFFFFF").1,
+            "0x7FFFFFFFFFFFFFFF + 0x7FFFFFFFFFFFFFFF is too large"
+        );
+        assert_eq!(
+            failure("LD V0, 0xFFFFFFFFFFFFFFFF").1,
+            "0xFFFFFFFFFFFFFFFF is too large"
+        );
+    }
+
+    #[test]
+    fn data_out_of_range() {
+        assert_eq!(
+            failure("db 1, 0x100"),
+            (1, "0x100 doesn't fit, the most is 0xFF".to_string())
+        );
+        assert_eq!(
+            failure("\ndw 0x10000"),
+            (2, "0x10000 doesn't fit, the most is 0xFFFF".to_string())
+        );
+    }
+
+    #[test]
+    fn undefined_symbol() {
+        assert_eq!(
+            failure("JP nowhere"),
+            (1, "Unknown symbol nowhere".to_string())
+        );
+    }
+
+    #[test]
+    fn duplicate_and_reserved_names() {
+        assert_eq!(
+            failure("a: CLS\na: CLS"),
+            (2, "a is already defined".to_string())
+        );
+        assert_eq!(
+            failure("X equ 1\nX equ 2"),
+            (2, "X is already defined".to_string())
+        );
+        assert_eq!(
+            failure("V3: CLS"),
+            (1, "V3 is reserved and can't be used as a name".to_string())
+        );
+    }
+
+    #[test]
+    fn constants_defined_in_terms_of_themselves() {
+        let (line, message) = failure("FOO equ BAR\nBAR equ FOO\nLD V0, FOO");
+        assert_eq!(line, 3);
+        assert!(message.starts_with("FOO is defined in terms of itself"));
+    }
+
+    #[test]
+    fn errors_point_at_the_constant() {
+        assert_eq!(
+            failure("BIG equ 0x1FF\n\nLD V0, BIG").1,
+            "0x1FF doesn't fit, the most is 0xFF"
+        );
+        assert_eq!(
+            failure("BAD equ nothing\nLD V0, BAD").1,
+            "Unknown symbol nothing (in BAD, defined at <source>:1)"
+        );
+    }
+}
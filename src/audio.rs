@@ -0,0 +1,124 @@
+//! Sample synthesis for the sound timer. Produces plain PCM that any audio backend can play.
+
+pub const SAMPLE_RATE: u32 = 44100;
+const PATTERN_BITS: usize = 128;
+
+/// Bits per second the XO-Chip audio pattern is played at for a value of the pitch register.
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// Renders the 1-bit XO-Chip audio pattern into at least `duration` seconds of samples.
+/// The buffer always holds a whole number of repetitions of the pattern so it can be looped.
+pub fn render_pattern(pattern: &[u8; 16], pitch: u8, volume: f32, duration: f32) -> Vec<i16> {
+    let rate = pattern_rate(pitch);
+    let pattern_duration = PATTERN_BITS as f32 / rate;
+    let repetitions = (duration / pattern_duration).ceil().max(1.0);
+    let sample_count = (repetitions * pattern_duration * SAMPLE_RATE as f32).round() as usize;
+    let amplitude = (volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16;
+
+    (0..sample_count)
+        .map(|i| {
+            let bit = (i as f32 * rate / SAMPLE_RATE as f32) as usize % PATTERN_BITS;
+            if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                amplitude
+            } else {
+                -amplitude
+            }
+        })
+        .collect()
+}
+
+/// Wraps mono 16-bit samples at SAMPLE_RATE in a WAV file.
+pub fn encode_wav(samples: &[i16]) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // pcm
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    wav.extend_from_slice(&2u16.to_le_bytes()); // block align
+    wav.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub const NAMES: [&'static str; 3] = ["square", "sine", "triangle"];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    // one cycle, phase going from 0 to 1, output between -1 and 1
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+/// The beep played while the sound timer is non-zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Renders at least `duration` seconds of the tone, cut at a whole number of cycles so it can be looped.
+pub fn render_tone(tone: &Tone, duration: f32) -> Vec<i16> {
+    let frequency = tone.frequency.max(1.0);
+    let cycles = (duration * frequency).ceil().max(1.0);
+    let sample_count = (cycles / frequency * SAMPLE_RATE as f32).round() as usize;
+    let amplitude = tone.volume.clamp(0.0, 1.0) * i16::MAX as f32;
+
+    (0..sample_count)
+        .map(|i| {
+            let phase = (i as f32 * frequency / SAMPLE_RATE as f32).fract();
+            (tone.waveform.sample(phase) * amplitude) as i16
+        })
+        .collect()
+}
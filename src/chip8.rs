@@ -1,35 +1,197 @@
 #![allow(dead_code, non_snake_case)]
 
-use macroquad::rand;
-use macroquad::file;
+use std::ops::RangeInclusive;
 
-#[derive(Debug)]
-enum Chip8Error {
+use crate::quirks::Quirks;
+use crate::rng::{Rng, XorShiftRng};
+use crate::state::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
     FileNotFound,
+    RomTooLarge { size: usize, max: usize },
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: usize },
+    InvalidState(&'static str),
+    InvalidMovie(&'static str),
 }
 
 impl std::fmt::Display for Chip8Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Chip8Error::FileNotFound => "File not found",
+        match self {
+            Chip8Error::FileNotFound => write!(f, "File not found"),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "Rom is {size} bytes, only {max} fit in memory")
+            }
+            Chip8Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {opcode:04X} at {pc:03X}")
             }
-        )
+            Chip8Error::StackOverflow { pc } => write!(f, "Stack overflow at {pc:03X}"),
+            Chip8Error::StackUnderflow { pc } => write!(f, "Stack underflow at {pc:03X}"),
+            Chip8Error::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {addr:X}")
+            }
+            Chip8Error::InvalidState(reason) => write!(f, "Invalid save state: {reason}"),
+            Chip8Error::InvalidMovie(reason) => write!(f, "Invalid movie: {reason}"),
+        }
     }
 }
 
+impl std::error::Error for Chip8Error {}
+
+/// What happened when a single instruction was executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    /// `LD Vx, K` is blocking until a key is pressed. The pc has not moved.
+    WaitingForKey,
+    /// A sprite was drawn in low resolution with the `display_wait` quirk on, nothing more runs
+    /// until the next frame.
+    WaitingForDisplay,
+    /// The program ran `EXIT`. Nothing will run anymore.
+    Exited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Computer {
     Normal,
     Eti,
+    SuperChip,
+    XoChip,
+}
+
+//a data access an instruction made to ram. instruction fetches aren't included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read { addr: u16, value: u8 },
+    Write { addr: u16, old: u8, value: u8 },
+}
+
+impl MemoryAccess {
+    pub fn addr(&self) -> u16 {
+        match *self {
+            MemoryAccess::Read { addr, .. } | MemoryAccess::Write { addr, .. } => addr,
+        }
+    }
+}
+
+impl Computer {
+    pub const NAMES: [&'static str; 4] = ["chip8", "eti", "schip", "xochip"];
+
+    pub fn from_name(name: &str) -> Option<Computer> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "normal" => Some(Computer::Normal),
+            "eti" => Some(Computer::Eti),
+            "schip" | "superchip" => Some(Computer::SuperChip),
+            "xochip" | "xo-chip" => Some(Computer::XoChip),
+            _ => None,
+        }
+    }
+
+    //the quirks programs written for this computer expect, unless told otherwise
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Computer::Normal | Computer::Eti => Quirks::default(),
+            Computer::SuperChip => Quirks::SUPER_CHIP,
+            Computer::XoChip => Quirks::MODERN,
+        }
+    }
+
+    //stable numbering used in save states
+    pub fn id(&self) -> u8 {
+        match self {
+            Computer::Normal => 0,
+            Computer::Eti => 1,
+            Computer::SuperChip => 2,
+            Computer::XoChip => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Computer> {
+        match id {
+            0 => Some(Computer::Normal),
+            1 => Some(Computer::Eti),
+            2 => Some(Computer::SuperChip),
+            3 => Some(Computer::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn ram_size(&self) -> usize {
+        match self {
+            Computer::XoChip => XO_RAM_SIZE,
+            _ => RAM_SIZE,
+        }
+    }
+
+    /// Guesses the computer a ROM was written for, from its extension (`.eti`, `.sc8` and `.xo8`)
+    /// or, failing that, from the addresses its instructions use. ETI 660 programs never refer to
+    /// the interpreter's memory between 0x200 and 0x600, programs loaded at 0x200 nearly always do.
+    pub fn detect(filename: &str, rom: &[u8]) -> Computer {
+        let extension = std::path::Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("eti") => return Computer::Eti,
+            Some("sc8") => return Computer::SuperChip,
+            Some("xo8") => return Computer::XoChip,
+            _ => {}
+        }
+
+        let eti_program = ETI_PROGRAM_START_LOCATION..ETI_PROGRAM_START_LOCATION + rom.len();
+        let (mut below_eti, mut in_eti) = (0, 0);
+        for word in rom.chunks_exact(2) {
+            let opcode = (word[0] as u16) << 8 | word[1] as u16;
+            // jumps, calls and loads of I
+            if !matches!(opcode >> 12, 0x1 | 0x2 | 0xA) {
+                continue;
+            }
+            let target = (opcode & 0xFFF) as usize;
+            if (PROGRAM_START_LOCATION..ETI_PROGRAM_START_LOCATION).contains(&target) {
+                below_eti += 1;
+            } else if eti_program.contains(&target) {
+                in_eti += 1;
+            }
+        }
+        match (below_eti, in_eti) {
+            (0, 2..) => Computer::Eti,
+            _ => Computer::Normal,
+        }
+    }
+
+    //where the rom is loaded and execution starts. the eti 660 keeps its interpreter below 0x600
+    pub fn program_start(&self) -> usize {
+        match self {
+            Computer::Normal | Computer::SuperChip | Computer::XoChip => PROGRAM_START_LOCATION,
+            Computer::Eti => ETI_PROGRAM_START_LOCATION,
+        }
+    }
 }
 
-const PROGRAM_START_LOCATION: usize = 0x200;
-const ETI_PROGRAM_START_LOCATION: usize = 0x600;
+pub const PROGRAM_START_LOCATION: usize = 0x200;
+pub const ETI_PROGRAM_START_LOCATION: usize = 0x600;
 const TEXT_MEMORY_START: usize = 0x000;
+const BIG_TEXT_MEMORY_START: usize = 0x050;
 pub const DISPLAY_WIDTH: u8 = 64;
 pub const DISPLAY_HEIGHT: u8 = 32;
+pub const HIRES_DISPLAY_WIDTH: u8 = 128;
+pub const HIRES_DISPLAY_HEIGHT: u8 = 64;
+const RPL_FLAG_COUNT: usize = 16;
+const RAM_SIZE: usize = 0x1000;
+const XO_RAM_SIZE: usize = 0x10000;
+const PLANE_COUNT: u8 = 2;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+const DEFAULT_PITCH: u8 = 64;
+//a 500hz square wave at the default pitch, until the program loads its own pattern
+const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_SIZE] = [0xF0; AUDIO_PATTERN_SIZE];
+pub const TIMER_FREQUENCY: u32 = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 700;
+//the speeds the interpreter runs at. the top is a few thousand times the fastest real
+//interpreter, and still a whole frame's work well within a frame
+pub const INSTRUCTIONS_PER_SECOND_RANGE: RangeInclusive<u32> = 1..=1_000_000;
 
 #[rustfmt::skip]
 const TEXT_ARRAY: [u8; 80] = [
@@ -51,12 +213,32 @@ const TEXT_ARRAY: [u8; 80] = [
     0xf0, 0x80, 0xf0, 0x80, 0x80, //F
 ];
 
-fn load_text(offset: usize) -> [u8; 4096] {
-    let mut ram = [0x0; 4096];
+//the 8x10 super-chip digits. the original only had 0-9, a-f are the ones octo added
+#[rustfmt::skip]
+const BIG_TEXT_ARRAY: [u8; 160] = [
+    0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, //0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff, //1
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, //2
+    0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, //3
+    0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03, //4
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, //5
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, //6
+    0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18, //7
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, //8
+    0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff, //9
+    0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, //A
+    0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, //B
+    0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c, //C
+    0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, //D
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, //E
+    0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0, //F
+];
 
-    for i in offset..TEXT_ARRAY.len() {
-        ram[i] = TEXT_ARRAY[i - offset];
-    }
+fn load_text(size: usize, offset: usize, big_offset: usize) -> Vec<u8> {
+    let mut ram = vec![0x0; size];
+
+    ram[offset..offset + TEXT_ARRAY.len()].copy_from_slice(&TEXT_ARRAY);
+    ram[big_offset..big_offset + BIG_TEXT_ARRAY.len()].copy_from_slice(&BIG_TEXT_ARRAY);
     ram
 }
 
@@ -73,7 +255,7 @@ fn get_bits(addr: u16) -> (u8, u8, u8, u8, u8, u8, u16) {
 }
 
 pub struct Chip8 {
-    pub ram: [u8; 4096],
+    pub ram: Vec<u8>,
     regs: [u8; 16],   //general purpose registers. but the last one is reserved
     ireg: u16,        //i reg. used to store memory addresses
     dreg: u8,         // delay timer register
@@ -82,72 +264,331 @@ pub struct Chip8 {
     sp: u8,           // stack pointer (index to stack)
     stack: [u16; 16], // stack. array of pointers
     pub kb: [u8; 16], // the keyboard
-    pub display: [u8; DISPLAY_WIDTH as usize * DISPLAY_HEIGHT as usize],
+    // sized for hires. in lores only the first DISPLAY_WIDTH * DISPLAY_HEIGHT pixels are used.
+    // each pixel is a bitmask of the xo-chip planes it is lit on, bit 0 being plane 1
+    pub display: [u8; HIRES_DISPLAY_WIDTH as usize * HIRES_DISPLAY_HEIGHT as usize],
+    hires: bool,      // super-chip 128x64 mode
+    planes: u8,       // xo-chip bitplanes selected for drawing
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE], // xo-chip 1-bit sample played while the sound timer runs
+    pitch: u8,        // xo-chip playback rate of audio_pattern
+    rpl: [u8; RPL_FLAG_COUNT], // super-chip rpl user flags
+    exited: bool,
+    computer: Computer,
+    quirks: Quirks,
+    ips: u32,         // instructions executed per second
+    cycle_remainder: u32, // leftover instruction budget carried between frames
+    rng: Box<dyn Rng>,
+    accesses: Vec<MemoryAccess>, // ram reads and writes of the last instruction
 }
 
 impl Chip8 {
-    pub async fn from_rom(comp: Computer, rom: String) -> Chip8 {
+    pub fn from_bytes(comp: Computer, quirks: Quirks, rom: &[u8]) -> Result<Chip8, Chip8Error> {
         let mut chip8 = Chip8 {
-            ram: load_text(TEXT_MEMORY_START),
+            ram: load_text(comp.ram_size(), TEXT_MEMORY_START, BIG_TEXT_MEMORY_START),
             regs: [0x0; 16],
             ireg: 0x00,
             dreg: 0x00,
             sreg: 0x0,
-            pc: match comp {
-                Computer::Normal => PROGRAM_START_LOCATION,
-                Computer::Eti => ETI_PROGRAM_START_LOCATION,
-            } as u16,
+            pc: comp.program_start() as u16,
             sp: 0x0,
             stack: [0x00; 16],
             kb: [0x0; 16],
-            display: [0; DISPLAY_WIDTH as usize * DISPLAY_HEIGHT as usize],
+            display: [0; HIRES_DISPLAY_WIDTH as usize * HIRES_DISPLAY_HEIGHT as usize],
+            hires: false,
+            planes: 0x1,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            rpl: [0x0; RPL_FLAG_COUNT],
+            exited: false,
+            computer: comp,
+            quirks,
+            ips: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            cycle_remainder: 0,
+            rng: Box::new(XorShiftRng::default()),
+            accesses: Vec::new(),
         };
-        chip8.load_rom(rom.clone()).await.expect(&format!("Couldn't Load Rom. path: {rom}"));
-        chip8
+        chip8.load_rom(rom)?;
+        Ok(chip8)
     }
 
-    async fn load_rom(&mut self, path: String) -> Result<(), Chip8Error> {
-        let the_file: Vec<u8> = file::load_file(&path).await.map_err(|_| Chip8Error::FileNotFound)?;
+    fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let start = self.computer.program_start();
+        let max = self.ram.len() - start;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.ram[start..start + rom.len()].copy_from_slice(rom);
 
-        for (dst, src) in self
-            .ram
-            .iter_mut()
-            .skip(PROGRAM_START_LOCATION)
-            .zip(&the_file)
-        {
-            *dst = *src;
+        Ok(())
+    }
+
+    pub fn set_rng(&mut self, rng: Box<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    //restarts the default generator from a known seed, so RND gives the same bytes on every run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Box::new(XorShiftRng::new(seed));
+    }
+
+    //snapshot of the whole machine, see the state module for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.u8(self.computer.id());
+        w.u8(self.quirks.to_bits());
+        w.u32(self.ram.len() as u32);
+        w.bytes(&self.ram);
+        w.bytes(&self.regs);
+        w.u16(self.ireg);
+        w.u8(self.dreg);
+        w.u8(self.sreg);
+        w.u16(self.pc);
+        w.u8(self.sp);
+        for addr in self.stack {
+            w.u16(addr);
         }
+        w.bytes(&self.kb);
+        w.bytes(&self.display);
+        w.bool(self.hires);
+        w.u8(self.planes);
+        w.bytes(&self.audio_pattern);
+        w.u8(self.pitch);
+        w.bytes(&self.rpl);
+        w.bool(self.exited);
+        w.u32(self.ips);
+        w.u32(self.cycle_remainder);
+        w.u64(self.rng.state());
+        w.finish()
+    }
 
+    //restores a snapshot made by save_state. on error the machine is left untouched
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Chip8Error> {
+        let mut r = StateReader::new(data)?;
+        let computer =
+            Computer::from_id(r.u8()?).ok_or(Chip8Error::InvalidState("unknown computer"))?;
+        let quirks = Quirks::from_bits(r.u8()?);
+        let ram_size = r.u32()? as usize;
+        if ram_size != computer.ram_size() {
+            return Err(Chip8Error::InvalidState("memory size doesn't match the computer"));
+        }
+        let ram = r.bytes(ram_size)?.to_vec();
+        let regs = r.array()?;
+        let ireg = r.u16()?;
+        let dreg = r.u8()?;
+        let sreg = r.u8()?;
+        let pc = r.u16()?;
+        let sp = r.u8()?;
+        let mut stack = [0x00; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        if sp as usize >= stack.len() {
+            return Err(Chip8Error::InvalidState("stack pointer out of range"));
+        }
+        let kb = r.array()?;
+        let display = r.array()?;
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        let audio_pattern = r.array()?;
+        let pitch = r.u8()?;
+        let rpl = r.array()?;
+        let exited = r.bool()?;
+        let ips = r.u32()?;
+        let cycle_remainder = r.u32()?;
+        let rng_state = r.u64()?;
+        r.finish()?;
+        if !INSTRUCTIONS_PER_SECOND_RANGE.contains(&ips) || cycle_remainder >= TIMER_FREQUENCY {
+            return Err(Chip8Error::InvalidState("speed out of range"));
+        }
+
+        self.computer = computer;
+        self.quirks = quirks;
+        self.ram = ram;
+        self.regs = regs;
+        self.ireg = ireg;
+        self.dreg = dreg;
+        self.sreg = sreg;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack = stack;
+        self.kb = kb;
+        self.display = display;
+        self.hires = hires;
+        self.planes = planes;
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.ips = ips;
+        self.cycle_remainder = cycle_remainder;
+        self.accesses.clear();
+        self.rng.set_state(rng_state);
         Ok(())
     }
 
+    pub fn computer(&self) -> Computer {
+        self.computer
+    }
+
+    pub fn regs(&self) -> &[u8; 16] {
+        &self.regs
+    }
+
+    pub fn ireg(&self) -> u16 {
+        self.ireg
+    }
+
+    pub fn dreg(&self) -> u8 {
+        self.dreg
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    //return addresses of the calls in progress, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp as usize]
+    }
+
+    //the resolution the program is currently drawing at
+    pub fn display_width(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_WIDTH as usize
+        } else {
+            DISPLAY_WIDTH as usize
+        }
+    }
+
+    pub fn display_height(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_HEIGHT as usize
+        } else {
+            DISPLAY_HEIGHT as usize
+        }
+    }
+
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    //what the instruction last run by tick read from and wrote to ram, in the order it happened
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    //clamped into INSTRUCTIONS_PER_SECOND_RANGE
+    pub fn set_instructions_per_second(&mut self, ips: u32) {
+        let range = INSTRUCTIONS_PER_SECOND_RANGE;
+        self.ips = ips.clamp(*range.start(), *range.end());
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.ips
+    }
+
+    //runs one 60hz frame: executes this frame's share of the cpu rate, then counts the timers down
+    //any fraction of an instruction left over is carried into the next frame
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    //like run_frame, but asks should_break before every instruction. if it says yes the frame is
+    //abandoned right there, without ticking the timers, and true is returned
+    pub fn run_frame_until(
+        &mut self,
+        mut should_break: impl FnMut(&Chip8) -> bool,
+    ) -> Result<bool, Chip8Error> {
+        let budget = self.ips.saturating_add(self.cycle_remainder);
+        self.cycle_remainder = budget % TIMER_FREQUENCY;
+        for _ in 0..budget / TIMER_FREQUENCY {
+            if should_break(self) {
+                return Ok(true);
+            }
+            //neither the keyboard nor the vblank can change until the next frame, so stop spinning
+            if self.tick()? != StepOutcome::Executed {
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(false)
+    }
+
+    //decrements the delay and sound timers. must be called at TIMER_FREQUENCY
+    pub fn tick_timers(&mut self) {
+        self.dreg = self.dreg.saturating_sub(1);
+        self.sreg = self.sreg.saturating_sub(1);
+    }
+
     //executes the instruction on pc and changes all the state
     //ram[pc] + ram[pc + 1]
-    pub fn tick(&mut self) {
-        
-        let instruction =
-        ((self.ram[self.pc as usize] as u16) << 8) | self.ram[self.pc as usize + 1] as u16;
-        
-        if self.dreg > 0 {
-            self.dreg = self.dreg.saturating_sub(1);
-        }
-        if self.sreg > 0 {
-            self.sreg = self.sreg.saturating_sub(1);
+    pub fn tick(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
         }
+        let instruction = ((self.read_byte(self.pc as usize)? as u16) << 8)
+            | self.read_byte(self.pc as usize + 1)? as u16;
+        self.accesses.clear();
 
         let (first, last, x, y, n, kk, nnn) = get_bits(instruction);
+        let invalid = Chip8Error::InvalidOpcode {
+            pc: self.pc,
+            opcode: instruction,
+        };
+
+        let xo = self.computer == Computer::XoChip;
+        //xo-chip is a superset of the super-chip
+        let schip = xo || self.computer == Computer::SuperChip;
 
         match first {
             0x0 => match instruction {
                 0x00E0 => self.CLS(),
-                0x00EE => self.RET(),
+                0x00EE => self.RET()?,
+                0x00C0..=0x00CF if schip => self.SCD(n),
+                0x00D0..=0x00DF if xo => self.SCU(n),
+                0x00FB if schip => self.SCR(),
+                0x00FC if schip => self.SCL(),
+                0x00FD if schip => {
+                    self.EXIT();
+                    return Ok(StepOutcome::Exited);
+                }
+                0x00FE if schip => self.LOW(),
+                0x00FF if schip => self.HIGH(),
                 _ => self.SYS(nnn),
             },
             0x1 => self.JP(nnn),
-            0x2 => self.CALL(nnn),
+            0x2 => self.CALL(nnn)?,
             0x3 => self.SE(x, kk),
             0x4 => self.SNE(x, kk),
-            0x5 => self.SER(x, y),
+            0x5 => match last {
+                0x0 => self.SER(x, y),
+                0x2 if xo => self.SAVE(x, y)?,
+                0x3 if xo => self.LOAD(x, y)?,
+                _ => return Err(invalid),
+            },
             0x6 => self.LD(x, kk),
             0x7 => self.ADD(x, kk),
             0x8 => match last {
@@ -160,31 +601,86 @@ impl Chip8 {
                 0x6 => self.SHR(x, y),
                 0x7 => self.SUBN(x, y),
                 0xE => self.SHL(x, y),
-                _ => panic!(),
+                _ => return Err(invalid),
+            },
+            0x9 => match last {
+                0x0 => self.SNER(x, y),
+                _ => return Err(invalid),
             },
-            0x9 => self.SNER(x, y),
             0xA => self.LDI(nnn),
             0xB => self.JPO(nnn),
             0xC => self.RND(x, kk),
-            0xD => self.DRW(x, y, n),
+            0xD => {
+                self.DRW(x, y, n)?;
+                if self.quirks.display_wait && !self.hires {
+                    return Ok(StepOutcome::WaitingForDisplay);
+                }
+            }
             0xE => match kk {
                 0x9E => self.SKPK(x),
                 0xA1 => self.SKNPK(x),
-                _ => panic!(),
+                _ => return Err(invalid),
             },
             0xF => match kk {
+                0x00 if xo && x == 0 => self.LDIL()?,
+                0x01 if xo => self.PLANE(x),
+                0x02 if xo && x == 0 => self.AUDIO()?,
                 0x07 => self.LDT(x),
-                0x0A => self.LDK(x),
+                0x0A => return Ok(self.LDK(x)),
                 0x15 => self.LDD(x),
                 0x18 => self.LDS(x),
                 0x1E => self.ADDI(x),
                 0x29 => self.LDF(x),
-                0x33 => self.LDB(x),
-                0x55 => self.LDIX(x),
-                0x65 => self.LDRX(x),
-                _ => panic!(),
+                0x30 if schip => self.LDHF(x),
+                0x33 => self.LDB(x)?,
+                0x3A if xo => self.PITCH(x),
+                0x55 => self.LDIX(x)?,
+                0x65 => self.LDRX(x)?,
+                0x75 if schip => self.LDRPL(x),
+                0x85 if schip => self.LDXRPL(x),
+                _ => return Err(invalid),
             },
-            _ => panic!(),
+            _ => return Err(invalid),
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    //skips the next instruction. xo-chip's F000 nnnn is 4 bytes long so it has to be skipped whole
+    fn skip_next(&mut self) {
+        self.pc = self.pc.wrapping_add(2);
+        let next = self.pc as usize;
+        let long = self.computer == Computer::XoChip
+            && self.ram.get(next) == Some(&0xF0)
+            && self.ram.get(next + 1) == Some(&0x00);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Chip8Error> {
+        let value = self
+            .ram
+            .get(addr)
+            .copied()
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr })?;
+        self.accesses.push(MemoryAccess::Read {
+            addr: addr as u16,
+            value,
+        });
+        Ok(value)
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        match self.ram.get_mut(addr) {
+            Some(dst) => {
+                let old = *dst;
+                *dst = value;
+                self.accesses.push(MemoryAccess::Write {
+                    addr: addr as u16,
+                    old,
+                    value,
+                });
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryOutOfBounds { addr }),
         }
     }
 
@@ -194,23 +690,132 @@ impl Chip8 {
     /// It is ignored by modern interpreters.
     fn SYS(&mut self, _addr: u16) {
         //not implemented
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 00e0 - CLS
     /// Clear the display.
+    /// On the XO-Chip only the selected planes are cleared.
     fn CLS(&mut self) {
-        self.display[..].fill(0x0);
-        self.pc += 2;
+        let planes = self.planes;
+        self.display.iter_mut().for_each(|p| *p &= !planes);
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 00ee - RET
     /// Return from a subroutine
     /// The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-    fn RET(&mut self) {
+    fn RET(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.pc });
+        }
         self.pc = self.stack[self.sp as usize];
         self.sp -= 1;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// 00Cn - SCD nibble
+    /// Scroll the display down by n pixels. (Super-Chip)
+    /// Rows scrolled in at the top are blank. On the XO-Chip only the selected planes move.
+    fn SCD(&mut self, n: u8) {
+        let width = self.display_width();
+        let height = self.display_height();
+        let n = n as usize;
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let from = if y >= n {
+                    self.display[(y - n) * width + x]
+                } else {
+                    0
+                };
+                self.move_pixel(y * width + x, from);
+            }
+        }
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// 00Dn - SCU nibble
+    /// Scroll the display up by n pixels. (XO-Chip)
+    /// Rows scrolled in at the bottom are blank. Only the selected planes move.
+    fn SCU(&mut self, n: u8) {
+        let width = self.display_width();
+        let height = self.display_height();
+        let n = n as usize;
+        for y in 0..height {
+            for x in 0..width {
+                let from = if y + n < height {
+                    self.display[(y + n) * width + x]
+                } else {
+                    0
+                };
+                self.move_pixel(y * width + x, from);
+            }
+        }
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// 00FB - SCR
+    /// Scroll the display right by 4 pixels. (Super-Chip)
+    fn SCR(&mut self) {
+        let width = self.display_width();
+        for y in 0..self.display_height() {
+            for x in (0..width).rev() {
+                let from = if x >= 4 {
+                    self.display[y * width + x - 4]
+                } else {
+                    0
+                };
+                self.move_pixel(y * width + x, from);
+            }
+        }
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// 00FC - SCL
+    /// Scroll the display left by 4 pixels. (Super-Chip)
+    fn SCL(&mut self) {
+        let width = self.display_width();
+        for y in 0..self.display_height() {
+            for x in 0..width {
+                let from = if x + 4 < width {
+                    self.display[y * width + x + 4]
+                } else {
+                    0
+                };
+                self.move_pixel(y * width + x, from);
+            }
+        }
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    //scrolling helper: replaces the selected planes of a pixel with the ones of another pixel
+    fn move_pixel(&mut self, position: usize, from: u8) {
+        let planes = self.planes;
+        self.display[position] = (self.display[position] & !planes) | (from & planes);
+    }
+
+    /// 00FD - EXIT
+    /// Exit the interpreter. (Super-Chip)
+    /// The program counter is left where it is and no further instructions are executed.
+    fn EXIT(&mut self) {
+        self.exited = true;
+    }
+
+    /// 00FE - LOW
+    /// Switch to the 64x32 low resolution mode and clear the display. (Super-Chip)
+    fn LOW(&mut self) {
+        self.hires = false;
+        self.display[..].fill(0x0);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// 00FF - HIGH
+    /// Switch to the 128x64 high resolution mode and clear the display. (Super-Chip)
+    fn HIGH(&mut self) {
+        self.hires = true;
+        self.display[..].fill(0x0);
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 1nnn - JP addr
@@ -224,10 +829,14 @@ impl Chip8 {
     /// Call subroutine at nnn.
     /// The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
     /// The PC is then set to nnn.
-    fn CALL(&mut self, addr: u16) {
+    fn CALL(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize + 1 >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow { pc: self.pc });
+        }
         self.sp += 1;
         self.stack[self.sp as usize] = self.pc;
         self.pc = addr;
+        Ok(())
     }
 
     /// 3xkk - SE Vx, Byte
@@ -235,9 +844,9 @@ impl Chip8 {
     /// The interpreter compare register Vx to kk, and if they are equal, increments the program counter by 2.
     fn SE(&mut self, x: u8, kk: u8) {
         if self.regs[x as usize] == kk {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -246,9 +855,9 @@ impl Chip8 {
     /// The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
     fn SNE(&mut self, x: u8, kk: u8) {
         if self.regs[x as usize] != kk {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -257,10 +866,34 @@ impl Chip8 {
     /// The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
     fn SER(&mut self, x: u8, y: u8) {
         if self.regs[x as usize] == self.regs[y as usize] {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    /// 5xy2 - SAVE Vx - Vy
+    /// Store registers Vx through Vy in memory starting at location I. (XO-Chip)
+    /// The registers are stored in order, so if x > y they are written backwards. I is not changed.
+    fn SAVE(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        for i in 0..=x.abs_diff(y) as usize {
+            let reg = if x <= y { x as usize + i } else { x as usize - i };
+            self.write_byte(self.ireg as usize + i, self.regs[reg])?;
         }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// 5xy3 - LOAD Vx - Vy
+    /// Read registers Vx through Vy from memory starting at location I. (XO-Chip)
+    /// The registers are read in order, so if x > y they are read backwards. I is not changed.
+    fn LOAD(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        for i in 0..=x.abs_diff(y) as usize {
+            let reg = if x <= y { x as usize + i } else { x as usize - i };
+            self.regs[reg] = self.read_byte(self.ireg as usize + i)?;
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
     /// 6xkk - LD Vx, Byte
@@ -268,7 +901,7 @@ impl Chip8 {
     /// The interpreter puts the value kk into register Vx.
     fn LD(&mut self, x: u8, kk: u8) {
         self.regs[x as usize] = kk;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 7xkk = ADD Vx, Byte
@@ -277,7 +910,7 @@ impl Chip8 {
     fn ADD(&mut self, x: u8, kk: u8) {
         //self.regs[x as usize] += kk;
         self.regs[x as usize] = self.regs[x as usize].wrapping_add(kk);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy0 - LDR Vx, Vy
@@ -285,103 +918,96 @@ impl Chip8 {
     /// Stores the value of register Vy in register Vx.
     fn LDR(&mut self, x: u8, y: u8) {
         self.regs[x as usize] = self.regs[y as usize];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy1 - OR Vx, Vy
     /// Set Vx = Vx OR Vy
     /// Performs a bitwise Or on the values of Vx and Vy, then stores the result in Vx.
     fn OR(&mut self, x: u8, y: u8) {
-        self.regs[x as usize] = self.regs[x as usize] | self.regs[y as usize];
-        self.pc += 2;
+        self.regs[x as usize] |= self.regs[y as usize];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy2 - AND Vx, Vy
     /// Set Vx = Vx AND Vy
     /// Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
     fn AND(&mut self, x: u8, y: u8) {
-        self.regs[x as usize] = self.regs[x as usize] & self.regs[y as usize];
-        self.pc += 2;
+        self.regs[x as usize] &= self.regs[y as usize];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy3 - XOR Vx, Vy
     /// Set Vx = Vx XOR Vy
     /// Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
     fn XOR(&mut self, x: u8, y: u8) {
-        self.regs[x as usize] = self.regs[x as usize] ^ self.regs[y as usize];
-        self.pc += 2;
+        self.regs[x as usize] ^= self.regs[y as usize];
+        if self.quirks.vf_reset {
+            self.regs[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy4 = ADDR Vx, Vy
     /// Set Vx = Vx + Vy, set VF = carry
     /// The values of Vx and Vy are added together. If the result is greater than 8 bits VF is set to 1, Otherwise 0.
     /// Only the lowest 8 bits of the result are kept, and stored in Vx.
+    /// Like all the 8xy_ flags, VF is written after the result, so it wins when x is F.
     fn ADDR(&mut self, x: u8, y: u8) {
         let result: u16 = self.regs[x as usize] as u16 + self.regs[y as usize] as u16;
-        if result > u8::MAX as u16 {
-            self.regs[0xF] = 1;
-        } else {
-            self.regs[0xF] = 0;
-        }
-
         self.regs[x as usize] = (result & 0x00FF) as u8;
-        self.pc += 2;
+        self.regs[0xF] = (result > u8::MAX as u16) as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy5 = SUB Vx, Vy
     /// Set Vx = Vx - Vy, set VF = NOT borrow
-    /// If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
+    /// If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
     fn SUB(&mut self, x: u8, y: u8) {
-        if self.regs[x as usize] > self.regs[y as usize] {
-            self.regs[0xF] = 1;
-        } else {
-            self.regs[0xF] = 0;
-        }
-
+        let no_borrow = self.regs[x as usize] >= self.regs[y as usize];
         self.regs[x as usize] = self.regs[x as usize].wrapping_sub(self.regs[y as usize]);
-        self.pc += 2;
+        self.regs[0xF] = no_borrow as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy6 - SHR Vx {, Vy}
     /// Set Vx = Vx SHR 1
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-    fn SHR(&mut self, x: u8, _y: u8) {
-        if self.regs[x as usize] & 0x1 == 0x1 {
-            self.regs[0xF] = 1;
-        } else {
-            self.regs[0xF] = 0;
-        }
-
-        self.regs[x as usize] = self.regs[x as usize] >> 1;
-        self.pc += 2;
+    /// With the `shift_uses_vy` quirk Vy is shifted instead and the result stored in Vx.
+    fn SHR(&mut self, x: u8, y: u8) {
+        let source = if self.quirks.shift_uses_vy { y } else { x };
+        let value = self.regs[source as usize];
+        self.regs[x as usize] = value >> 1;
+        self.regs[0xF] = value & 0x1;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xy7 - SUBN Vx, Vy
     /// Set Vx = Vy - Vx, set VF = NOT borrow
-    /// If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the result is stored in Vx.
+    /// If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the result is stored in Vx.
     fn SUBN(&mut self, x: u8, y: u8) {
-        if self.regs[y as usize] > self.regs[x as usize] {
-            self.regs[0xF] = 1;
-        } else {
-            self.regs[0xF] = 0;
-        }
-
+        let no_borrow = self.regs[y as usize] >= self.regs[x as usize];
         self.regs[x as usize] = self.regs[y as usize].wrapping_sub(self.regs[x as usize]);
-        self.pc += 2;
+        self.regs[0xF] = no_borrow as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 8xyE - SHL Vx {, Vy}
     /// Set Vx = Vx SHL 1
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is multiplied by 2.
-    fn SHL(&mut self, x: u8, _y: u8) {
-        if self.regs[x as usize] & 0x80 != 0 {
-            self.regs[0xF] = 1;
-        } else {
-            self.regs[0xF] = 0;
-        }
-
-        self.regs[x as usize] = self.regs[x as usize] << 1;
-        self.pc += 2;
+    /// With the `shift_uses_vy` quirk Vy is shifted instead and the result stored in Vx.
+    fn SHL(&mut self, x: u8, y: u8) {
+        let source = if self.quirks.shift_uses_vy { y } else { x };
+        let value = self.regs[source as usize];
+        self.regs[x as usize] = value << 1;
+        self.regs[0xF] = value >> 7;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// 9xy0 - SNE Vx, Vy
@@ -389,9 +1015,9 @@ impl Chip8 {
     /// The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
     fn SNER(&mut self, x: u8, y: u8) {
         if self.regs[x as usize] != self.regs[y as usize] {
-            self.pc += 4;
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -400,23 +1026,30 @@ impl Chip8 {
     /// The value of register I is set to nnn.
     fn LDI(&mut self, addr: u16) {
         self.ireg = addr & 0xFFF;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Bnnn JPO V0, addr
     /// Jump to location nnn + V0
     /// The program counter is set to nnn plus the value of V0
+    /// With the `jump_uses_vx` quirk the register is Vx, x being the highest nibble of nnn.
     fn JPO(&mut self, addr: u16) {
-        self.pc = addr + self.regs[0] as u16;
+        let reg = if self.quirks.jump_uses_vx {
+            (addr >> 8) & 0xF
+        } else {
+            0
+        };
+        self.pc = addr + self.regs[reg as usize] as u16;
     }
 
     /// Cxkk - RND Vx, Byte
     /// Set Vx = random byte AND kk
     /// The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
     /// The results are stored in Vx.
+    /// The numbers come from the chip's Rng, which is seeded with `rng::DEFAULT_SEED` unless told otherwise.
     fn RND(&mut self, x: u8, kk: u8) {
-        self.regs[x as usize] = rand::gen_range(0u8, 255u8) & kk;
-        self.pc += 2;
+        self.regs[x as usize] = self.rng.next_byte() & kk;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Dxyn - DRW Vx, Vy, nibble
@@ -426,45 +1059,71 @@ impl Chip8 {
     /// to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the
     /// coordinates of the display, it wraps around to the opposite side of the screen. See instruction 8xy3 for more
     /// information on XOR, and section 2.4, Display, for more information on the Chip-8 screen and sprites.
-    fn DRW(&mut self, x: u8, y: u8, n: u8) {
+    /// With the `clip_sprites` quirk the parts of the sprite outside of the display are not drawn instead.
+    /// On the Super-Chip, Dxy0 draws a 16x16 sprite made of 32 bytes, two per row.
+    fn DRW(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
         let mut set_collision = false;
-        let posX = self.regs[x as usize] as u32;
-        let posY = self.regs[y as usize] as u32;
-        for i in 0..n as u32 {
-            let byte_index = self.ireg as usize + i as usize;
-            let mut current_byte = self.ram[byte_index];
-
-            for j in 0..8 as u32 {
-                let position = ((posY + i) % DISPLAY_HEIGHT as u32) * DISPLAY_WIDTH as u32
-                    + (posX + j) % DISPLAY_WIDTH as u32;
-
-                let current_bit = (current_byte & 0x80) >> 7;
-
-                if self.display[position as usize] != 0
-                    && self.display[position as usize] ^ current_bit == 0
-                {
-                    set_collision = true;
+        let width = self.display_width() as u32;
+        let height = self.display_height() as u32;
+        let big_sprites = matches!(self.computer, Computer::SuperChip | Computer::XoChip);
+        let (rows, columns) = if n == 0 && big_sprites {
+            (16, 16)
+        } else {
+            (n as u32, 8)
+        };
+        let bytes_per_row = columns as usize / 8;
+        //the starting position always wraps, only the pixels past the edge are affected by clipping
+        let posX = self.regs[x as usize] as u32 % width;
+        let posY = self.regs[y as usize] as u32 % height;
+        //with both xo-chip planes selected, the sprite for plane 2 follows the one for plane 1 in memory
+        let mut byte_index = self.ireg as usize;
+        let planes = self.planes;
+        for plane in (0..PLANE_COUNT).map(|p| 1 << p).filter(|p| planes & p != 0) {
+            for i in 0..rows {
+                let row_index = byte_index + i as usize * bytes_per_row;
+                if self.quirks.clip_sprites && posY + i >= height {
+                    break;
+                }
+                //sprite rows are left aligned in a u16, 8 wide sprites just leave the low byte empty
+                let mut current_row = (self.read_byte(row_index)? as u16) << 8;
+                if bytes_per_row == 2 {
+                    current_row |= self.read_byte(row_index + 1)? as u16;
+                }
+
+                for j in 0..columns {
+                    if self.quirks.clip_sprites && posX + j >= width {
+                        break;
+                    }
+                    let position = (((posY + i) % height) * width + (posX + j) % width) as usize;
+
+                    if current_row & 0x8000 != 0 {
+                        if self.display[position] & plane != 0 {
+                            set_collision = true;
+                        }
+                        self.display[position] ^= plane;
+                    }
+                    current_row <<= 1;
                 }
-                self.display[position as usize] ^= current_bit;
-                current_byte <<= 1;
             }
+            byte_index += rows as usize * bytes_per_row;
         }
         if set_collision {
             self.regs[0xF] = 1;
         } else {
             self.regs[0xF] = 0;
         }
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
     /// Ex9E - SKPK Vx
     /// Skip next instruction if key with the value of Vx is pressed
     /// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
     fn SKPK(&mut self, x: u8) {
-        if self.kb[self.regs[x as usize] as usize] != 0 {
-            self.pc += 4;
+        if self.kb[(self.regs[x as usize] & 0xF) as usize] != 0 {
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -472,11 +1131,40 @@ impl Chip8 {
     /// Skip the next instruction if key with the value of Vx is not pressed.
     /// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position, PC is increased by 2.
     fn SKNPK(&mut self, x: u8) {
-        if self.kb[self.regs[x as usize] as usize] == 0 {
-            self.pc += 4;
+        if self.kb[(self.regs[x as usize] & 0xF) as usize] == 0 {
+            self.skip_next();
         } else {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    /// F000 nnnn - LDIL I, addr
+    /// Set I = nnnn. (XO-Chip)
+    /// The 16 bit address is stored in the two bytes following the instruction, which is 4 bytes long.
+    fn LDIL(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.pc as usize;
+        self.ireg = ((self.read_byte(pc + 2)? as u16) << 8) | self.read_byte(pc + 3)? as u16;
+        self.pc = self.pc.wrapping_add(4);
+        Ok(())
+    }
+
+    /// Fn01 - PLANE n
+    /// Select the bitplanes drawn to by DRW, cleared by CLS and moved by the scroll instructions. (XO-Chip)
+    /// n is a bitmask, 1 selects the first plane, 2 the second, 3 both and 0 none.
+    fn PLANE(&mut self, n: u8) {
+        self.planes = n & 0x3;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// F002 - AUDIO
+    /// Load the 16 byte audio pattern from memory starting at location I. (XO-Chip)
+    /// The 128 bits of the pattern are played in a loop, one bit at a time, while the sound timer is non-zero.
+    fn AUDIO(&mut self) -> Result<(), Chip8Error> {
+        for i in 0..AUDIO_PATTERN_SIZE {
+            self.audio_pattern[i] = self.read_byte(self.ireg as usize + i)?;
         }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
     /// Fx07 - LDT Vx, DT
@@ -484,20 +1172,21 @@ impl Chip8 {
     /// The value of DT is placed into Vx.
     fn LDT(&mut self, x: u8) {
         self.regs[x as usize] = self.dreg;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx0A - LDK Vx, K
     /// Wait for a key press, store the value of the key in Vx
     /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
-    fn LDK(&mut self, x: u8) {
+    fn LDK(&mut self, x: u8) -> StepOutcome {
         for (i, val) in self.kb.iter().enumerate() {
             if val != &0 {
                 self.regs[x as usize] = i as u8;
-                self.pc += 2;
-                break;
+                self.pc = self.pc.wrapping_add(2);
+                return StepOutcome::Executed;
             }
         }
+        StepOutcome::WaitingForKey
     }
 
     /// Fx15 - LDD DT, Vx
@@ -505,7 +1194,7 @@ impl Chip8 {
     /// DT is set equal to value of Vx.
     fn LDD(&mut self, x: u8) {
         self.dreg = self.regs[x as usize];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx18 - LDS ST, Vx
@@ -513,15 +1202,15 @@ impl Chip8 {
     /// ST is set equal to the value of Vx.
     fn LDS(&mut self, x: u8) {
         self.sreg = self.regs[x as usize];
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx1E - ADDI I, Vx
     /// Set I = I + Vx
     /// The values of I and Vx are added, and the results are stored in I.
     fn ADDI(&mut self, x: u8) {
-        self.ireg += self.regs[x as usize] as u16;
-        self.pc += 2;
+        self.ireg = self.ireg.wrapping_add(self.regs[x as usize] as u16);
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx29 - LDF F, Vx
@@ -529,45 +1218,81 @@ impl Chip8 {
     /// The value of I is set to the location for the hexadecimal sprite corresponding to the value of Vx.
     fn LDF(&mut self, x: u8) {
         self.ireg = self.regs[x as usize] as u16 * 5 + TEXT_MEMORY_START as u16;
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    /// Fx30 - LDHF HF, Vx
+    /// Set I = Location of the 8x10 sprite for digit Vx. (Super-Chip)
+    fn LDHF(&mut self, x: u8) {
+        self.ireg = (self.regs[x as usize] & 0xF) as u16 * 10 + BIG_TEXT_MEMORY_START as u16;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx33 - LDB B, Vx
     /// Store BCD representation of Vx in memory locations I, I+1, and I+2
     /// The integer takes the decimal value of Vx, and places the hundreds digit in memory at location I, the tens digit in location I+1,
     /// and the ones digits at location I+2.
-    fn LDB(&mut self, x: u8) {
+    fn LDB(&mut self, x: u8) -> Result<(), Chip8Error> {
         let mut value = self.regs[x as usize];
         for i in (0..=2).rev() {
-            self.ram[(self.ireg + i) as usize] = value % 10;
+            self.write_byte(self.ireg as usize + i, value % 10)?;
             value /= 10;
         }
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// Fx3A - PITCH Vx
+    /// Set the audio pitch register = Vx. (XO-Chip)
+    /// The audio pattern plays at 4000*2^((pitch-64)/48) bits per second.
+    fn PITCH(&mut self, x: u8) {
+        self.pitch = self.regs[x as usize];
+        self.pc = self.pc.wrapping_add(2);
     }
 
     /// Fx55 - LDIX [I], Vx
     /// Store registers V0 through Vx in memory starting at location I.
     /// The interpreter copies the values of registers v0 through Vx into memory, starting at the address in I.
-    fn LDIX(&mut self, x: u8) {
+    /// With the `load_store_increments_i` quirk I is left at I + x + 1.
+    fn LDIX(&mut self, x: u8) -> Result<(), Chip8Error> {
         for (i, val) in self.regs.into_iter().take((x + 1) as usize).enumerate() {
-            self.ram[self.ireg as usize + i] = val;
+            self.write_byte(self.ireg as usize + i, val)?;
         }
-        self.pc += 2;
+        if self.quirks.load_store_increments_i {
+            self.ireg = self.ireg.wrapping_add(x as u16 + 1);
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
     /// Fx65 - LDRX Vx, [I]
     /// Read registers V0 through Vx from memory starting at location I
     /// The interpreter reads values from memory starting at location I into registers V0 through Vx.
-    fn LDRX(&mut self, x: u8) {
-        for (i, val) in self
-            .ram
-            .into_iter()
-            .skip(self.ireg as usize)
-            .take((x + 1) as usize)
-            .enumerate()
-        {
-            self.regs[i] = val;
+    /// With the `load_store_increments_i` quirk I is left at I + x + 1.
+    fn LDRX(&mut self, x: u8) -> Result<(), Chip8Error> {
+        for i in 0..=x as usize {
+            self.regs[i] = self.read_byte(self.ireg as usize + i)?;
         }
-        self.pc += 2;
+        if self.quirks.load_store_increments_i {
+            self.ireg = self.ireg.wrapping_add(x as u16 + 1);
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// Fx75 - LDRPL R, Vx
+    /// Store registers V0 through Vx in the RPL user flags. (Super-Chip)
+    fn LDRPL(&mut self, x: u8) {
+        let count = (x as usize + 1).min(RPL_FLAG_COUNT);
+        self.rpl[..count].copy_from_slice(&self.regs[..count]);
+        self.pc = self.pc.wrapping_add(2);
     }
-}
\ No newline at end of file
+
+    /// Fx85 - LDXRPL Vx, R
+    /// Read registers V0 through Vx from the RPL user flags. (Super-Chip)
+    fn LDXRPL(&mut self, x: u8) {
+        let count = (x as usize + 1).min(RPL_FLAG_COUNT);
+        self.regs[..count].copy_from_slice(&self.rpl[..count]);
+        self.pc = self.pc.wrapping_add(2);
+    }
+}
@@ -0,0 +1,400 @@
+// the command line, parsed before a window is opened so mistakes are reported instead of panicking
+
+use chippi::audio::{Tone, Waveform};
+use chippi::chip8::{Computer, INSTRUCTIONS_PER_SECOND_RANGE};
+use chippi::headless::{KeyScript, Limit};
+use chippi::quirks::Quirks;
+
+use crate::config::palette_from_name;
+
+pub const USAGE: &str = "\
+Usage: chippi [run] [<rom>] [options]
+       chippi disasm <rom> [--computer <name>]
+       chippi assemble <source> [-o <rom>] [--computer <name>]
+       chippi info <rom> [--computer <name>]
+       chippi <command> --help
+
+Running:
+  --rom <file>             the ROM or .8o source to run (default roms/chippi.ch8)
+  --computer <name>        chip8, eti, schip or xochip (guessed from the ROM when left out)
+  --quirks <name>          vip, chip48, schip or modern (default: what the computer expects)
+  --speed <1-20>           multiplies --ips (default 1)
+  --ips <1-1000000>        instructions per second (default 700, or what the ROM needs)
+  --seed <n>               seed for RND, the same seed and input play out the same
+  --palette <name>         green, red, white, blue, yellow or rainbow
+  --scale <n>              pixels per CHIP-8 pixel (default 20)
+  --fullscreen             start in fullscreen
+  --mute                   no sound
+  --waveform <name>        square, sine or triangle beep
+  --frequency <hz>         pitch of the beep (default 440)
+  --volume <0.0-1.0>       volume of the beep (default 0.25)
+  --buzz-dir <dir>         play buzz1.wav..buzz3.wav from dir instead of the beep
+  --record <file>          record the keyboard to a movie file
+  --replay <file>          play a movie file back with the settings it was recorded with
+  --tty                    play in the terminal instead of a window
+  ROMs in the built-in database get the computer, quirks, --ips and palette they need from it,
+  chippi info shows what's known about a ROM. The window and --tty also fall back on config.toml
+  in the user's config directory for --computer, --quirks, --speed and --palette, see the README.
+
+Headless (--headless), for scripts and CI:
+  --frames <n>             frames to run (default 60)
+  --instructions <n>       instructions to run instead
+  --keys <script>          keys to hold, e.g. 60-65:5,120:AB (repeatable)
+  --ascii <file|->         write the display as text
+  --png <file>             write the display as a PNG
+  --json <file|->          write the registers as JSON
+  With none of --ascii, --png and --json the display is printed. The seed defaults to a fixed one.
+";
+
+const DISASM_USAGE: &str = "\
+Usage: chippi disasm <rom> [--computer <name>]
+
+Prints a listing of the ROM that assembles back into it. Without --computer the ROM is looked at to
+guess which computer it was written for.
+";
+
+const ASSEMBLE_USAGE: &str = "\
+Usage: chippi assemble <source> [-o <rom>] [--computer <name>]
+
+Assembles source into a ROM, written next to it with a .ch8 extension unless -o says otherwise.
+.8o sources are compiled as Octo.
+";
+
+const INFO_USAGE: &str = "\
+Usage: chippi info <rom> [--computer <name>]
+
+Prints the size and SHA-1 of a ROM and the computer and quirks it would run with.
+";
+
+// the colours of ALL_COLORS in program.rs, in the same order
+pub const PALETTE_NAMES: [&str; 5] = ["green", "red", "white", "blue", "yellow"];
+// what they look like, to match the colours of the rom database against
+pub const PALETTE_RGB: [[u8; 3]; 5] = [
+    [0, 228, 48],
+    [230, 41, 55],
+    [255, 255, 255],
+    [0, 121, 241],
+    [253, 249, 0],
+];
+pub const MAX_SPEED: usize = 20;
+const DEFAULT_SCALE: i32 = 20;
+const DEFAULT_FRAMES: u64 = 60;
+
+pub enum Command {
+    Run(RunOptions),
+    Tty(RunOptions),
+    Headless(RunOptions, HeadlessOptions),
+    Disasm {
+        rom: String,
+        computer: Option<Computer>,
+    },
+    Assemble {
+        source: String,
+        output: Option<String>,
+        computer: Option<Computer>,
+    },
+    Info {
+        rom: String,
+        computer: Option<Computer>,
+    },
+    Help(&'static str),
+}
+
+// everything that decides how a rom runs, whichever front-end runs it
+pub struct RunOptions {
+    pub rom: Option<String>,
+    pub computer: Option<Computer>,
+    pub quirks: Option<Quirks>,
+    // None for what the config says, or 1
+    pub speed: Option<usize>,
+    // None for what the rom database says, or 700
+    pub ips: Option<u32>,
+    pub seed: Option<u64>,
+    // an index into PALETTE_NAMES
+    pub color: usize,
+    // None when not picked: rainbow for the demo rom, plain for anything else
+    pub rainbow: Option<bool>,
+    pub scale: i32,
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub tone: Tone,
+    pub buzz_dir: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            rom: None,
+            computer: None,
+            quirks: None,
+            speed: None,
+            ips: None,
+            seed: None,
+            color: 0,
+            rainbow: None,
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            mute: false,
+            tone: Tone::default(),
+            buzz_dir: None,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+pub struct HeadlessOptions {
+    pub limit: Limit,
+    pub keys: KeyScript,
+    pub ascii: Option<String>,
+    pub png: Option<String>,
+    pub json: Option<String>,
+}
+
+// the values of options, taken one argument at a time
+struct Args<'a> {
+    args: std::slice::Iter<'a, String>,
+}
+
+impl<'a> Args<'a> {
+    fn value(&mut self, option: &str) -> Result<&'a str, String> {
+        self.args
+            .next()
+            .map(String::as_str)
+            .ok_or(format!("{option} needs a value"))
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, option: &str, expected: &str) -> Result<T, String> {
+        let value = self.value(option)?;
+        value
+            .parse()
+            .map_err(|_| format!("{option} expects {expected}, not '{value}'"))
+    }
+
+    fn choice<T>(
+        &mut self,
+        option: &str,
+        from_name: impl Fn(&str) -> Option<T>,
+        names: &[&str],
+    ) -> Result<T, String> {
+        let value = self.value(option)?;
+        from_name(value).ok_or_else(|| {
+            format!(
+                "{option} expects one of {}, not '{value}'",
+                names.join(", ")
+            )
+        })
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = match args.split_first() {
+        Some((first, rest)) if !first.starts_with('-') && is_command(first) => {
+            (first.as_str(), rest)
+        }
+        _ => ("run", args),
+    };
+    let mut args = Args { args: rest.iter() };
+    match command {
+        "disasm" | "disassemble" => {
+            let (rom, computer) = parse_rom_command(&mut args, command)?;
+            Ok(match rom {
+                Some(rom) => Command::Disasm { rom, computer },
+                None => Command::Help(DISASM_USAGE),
+            })
+        }
+        "info" => {
+            let (rom, computer) = parse_rom_command(&mut args, command)?;
+            Ok(match rom {
+                Some(rom) => Command::Info { rom, computer },
+                None => Command::Help(INFO_USAGE),
+            })
+        }
+        "assemble" | "asm" => parse_assemble(&mut args),
+        "help" => Ok(Command::Help(match rest.first().map(String::as_str) {
+            Some("disasm" | "disassemble") => DISASM_USAGE,
+            Some("assemble" | "asm") => ASSEMBLE_USAGE,
+            Some("info") => INFO_USAGE,
+            _ => USAGE,
+        })),
+        _ => parse_run(&mut args),
+    }
+}
+
+fn is_command(arg: &str) -> bool {
+    matches!(
+        arg,
+        "run" | "disasm" | "disassemble" | "assemble" | "asm" | "info" | "help"
+    )
+}
+
+fn parse_computer(args: &mut Args, option: &str) -> Result<Computer, String> {
+    args.choice(option, Computer::from_name, &Computer::NAMES)
+}
+
+// a rom and --computer, what disasm and info take
+fn parse_rom_command(
+    args: &mut Args,
+    command: &str,
+) -> Result<(Option<String>, Option<Computer>), String> {
+    let mut rom = None;
+    let mut computer = None;
+    while let Some(arg) = args.args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok((None, None)),
+            "--computer" => computer = Some(parse_computer(args, arg)?),
+            _ if arg.starts_with('-') => return Err(unknown_option(arg)),
+            _ if rom.is_some() => {
+                return Err(format!("Only one rom can be given, not also '{arg}'"))
+            }
+            _ => rom = Some(arg.clone()),
+        }
+    }
+    match rom {
+        Some(rom) => Ok((Some(rom), computer)),
+        None => Err(format!("{command} needs a rom")),
+    }
+}
+
+fn parse_assemble(args: &mut Args) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut computer = None;
+    while let Some(arg) = args.args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help(ASSEMBLE_USAGE)),
+            "-o" | "--output" => output = Some(args.value(arg)?.to_string()),
+            "--computer" => computer = Some(parse_computer(args, arg)?),
+            _ if arg.starts_with('-') => return Err(unknown_option(arg)),
+            _ if source.is_some() => {
+                return Err(format!("Only one source can be given, not also '{arg}'"))
+            }
+            _ => source = Some(arg.clone()),
+        }
+    }
+    let source = source.ok_or("assemble needs a source file")?;
+    Ok(Command::Assemble {
+        source,
+        output,
+        computer,
+    })
+}
+
+fn parse_run(args: &mut Args) -> Result<Command, String> {
+    let mut options = RunOptions::default();
+    let mut headless = HeadlessOptions {
+        limit: Limit::Frames(DEFAULT_FRAMES),
+        keys: KeyScript::default(),
+        ascii: None,
+        png: None,
+        json: None,
+    };
+    let (mut is_headless, mut is_tty) = (false, false);
+    // the headless options that were used, so they can be refused without --headless
+    let mut headless_option = None;
+
+    while let Some(arg) = args.args.next() {
+        let option = arg.as_str();
+        match option {
+            "-h" | "--help" => return Ok(Command::Help(USAGE)),
+            "--rom" => options.rom = Some(args.value(option)?.to_string()),
+            "--computer" => options.computer = Some(parse_computer(args, option)?),
+            "--quirks" => {
+                options.quirks =
+                    Some(args.choice(option, Quirks::from_name, &Quirks::PRESET_NAMES)?)
+            }
+            "--speed" => {
+                let expected = format!("a whole number from 1 to {MAX_SPEED}");
+                let speed = args.number(option, &expected)?;
+                if !(1..=MAX_SPEED).contains(&speed) {
+                    return Err(format!("{option} expects {expected}, not {speed}"));
+                }
+                options.speed = Some(speed);
+            }
+            "--ips" => {
+                let range = INSTRUCTIONS_PER_SECOND_RANGE;
+                let expected = format!("a whole number from {} to {}", range.start(), range.end());
+                let ips = args.number(option, &expected)?;
+                if !range.contains(&ips) {
+                    return Err(format!("{option} expects {expected}, not {ips}"));
+                }
+                options.ips = Some(ips);
+            }
+            "--seed" => options.seed = Some(args.number(option, "a whole number")?),
+            "--palette" => {
+                let mut names = PALETTE_NAMES.to_vec();
+                names.push("rainbow");
+                let (color, rainbow) = args.choice(option, palette_from_name, &names)?;
+                options.color = color;
+                options.rainbow = Some(rainbow);
+            }
+            "--scale" => {
+                options.scale = args.number(option, "a whole number of pixels")?;
+                if options.scale < 1 {
+                    return Err(format!("{option} must be at least 1"));
+                }
+            }
+            "--fullscreen" => options.fullscreen = true,
+            "--mute" => options.mute = true,
+            "--waveform" => {
+                options.tone.waveform =
+                    args.choice(option, Waveform::from_name, &Waveform::NAMES)?
+            }
+            "--frequency" => {
+                options.tone.frequency = args.number(option, "a frequency in hz")?;
+                if options.tone.frequency <= 0.0 {
+                    return Err(format!("{option} must be above 0"));
+                }
+            }
+            "--volume" => {
+                options.tone.volume = args.number(option, "a number from 0.0 to 1.0")?;
+                if !(0.0..=1.0).contains(&options.tone.volume) {
+                    return Err(format!("{option} expects a number from 0.0 to 1.0"));
+                }
+            }
+            "--buzz-dir" => options.buzz_dir = Some(args.value(option)?.to_string()),
+            "--record" => options.record = Some(args.value(option)?.to_string()),
+            "--replay" => options.replay = Some(args.value(option)?.to_string()),
+            "--tty" => is_tty = true,
+            "--headless" => is_headless = true,
+            "--frames" | "--instructions" | "--keys" | "--ascii" | "--png" | "--json" => {
+                headless_option = Some(option);
+                match option {
+                    "--frames" => {
+                        headless.limit = Limit::Frames(args.number(option, "a whole number")?)
+                    }
+                    "--instructions" => {
+                        headless.limit = Limit::Instructions(args.number(option, "a whole number")?)
+                    }
+                    "--keys" => headless.keys.extend(KeyScript::parse(args.value(option)?)?),
+                    "--ascii" => headless.ascii = Some(args.value(option)?.to_string()),
+                    "--png" => headless.png = Some(args.value(option)?.to_string()),
+                    _ => headless.json = Some(args.value(option)?.to_string()),
+                }
+            }
+            _ if option.starts_with('-') => return Err(unknown_option(option)),
+            _ if options.rom.is_some() => {
+                return Err(format!("Only one rom can be given, not also '{option}'"))
+            }
+            _ => options.rom = Some(arg.clone()),
+        }
+    }
+
+    if let (Some(option), false) = (headless_option, is_headless) {
+        return Err(format!("{option} only works with --headless"));
+    }
+    match (is_headless, is_tty) {
+        (true, true) => Err("--headless and --tty can't be used together".to_string()),
+        (true, false) if options.rom.is_none() => Err("--headless needs a rom".to_string()),
+        (true, false) => Ok(Command::Headless(options, headless)),
+        (false, true) if options.rom.is_none() => Err("--tty needs a rom".to_string()),
+        (false, true) => Ok(Command::Tty(options)),
+        (false, false) => Ok(Command::Run(options)),
+    }
+}
+
+fn unknown_option(option: &str) -> String {
+    format!("Unknown option {option}")
+}
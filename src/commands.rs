@@ -0,0 +1,282 @@
+// the commands that don't open a window, see cli.rs for how they're picked
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chippi::asm;
+use chippi::chip8::{Computer, DEFAULT_INSTRUCTIONS_PER_SECOND, TIMER_FREQUENCY};
+use chippi::database::{Database, Entry};
+use chippi::disasm;
+use chippi::headless;
+use chippi::movie;
+use chippi::octo;
+use chippi::{Chip8, DEFAULT_SEED};
+
+use crate::cli::{Command, HeadlessOptions, RunOptions};
+use crate::config::{Config, RomSettings};
+
+// side of the png in pixels, whatever the resolution
+const PNG_WIDTH: usize = 512;
+
+// runs anything but the window, returning the exit code
+pub fn run(command: Command) -> i32 {
+    let (name, result) = match command {
+        Command::Run(_) => unreachable!("the window is opened by main"),
+        Command::Help(usage) => {
+            print!("{usage}");
+            return 0;
+        }
+        Command::Headless(options, headless) => ("headless", run_headless(options, headless)),
+        Command::Tty(options) => ("tty", run_tty(options).map(|()| 0)),
+        Command::Disasm { rom, computer } => ("disasm", disassemble(&rom, computer).map(|()| 0)),
+        Command::Assemble {
+            source,
+            output,
+            computer,
+        } => ("assemble", assemble(&source, output, computer).map(|()| 0)),
+        Command::Info { rom, computer } => ("info", info(&rom, computer).map(|()| 0)),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{name}: {e}");
+            1
+        }
+    }
+}
+
+fn disassemble(rom_filename: &str, computer: Option<Computer>) -> Result<(), String> {
+    let (rom, computer, _) = read_rom(Path::new(rom_filename), computer)?;
+    print!("{}", disasm::disassemble_rom(&rom, computer));
+    Ok(())
+}
+
+fn assemble(
+    source: &str,
+    output: Option<String>,
+    computer: Option<Computer>,
+) -> Result<(), String> {
+    let source = Path::new(source);
+    let computer = computer.unwrap_or(Computer::Normal);
+    let output = output.map_or_else(|| source.with_extension("ch8"), PathBuf::from);
+    let rom = match source.extension().and_then(|e| e.to_str()) {
+        Some("8o") => octo::compile_file(source, computer),
+        _ => asm::assemble_file(source, computer),
+    }
+    .map_err(|e| e.to_string())?;
+    fs::write(&output, &rom).map_err(|e| format!("Couldn't write {}: {e}", output.display()))?;
+    println!("{} bytes written to {}", rom.len(), output.display());
+    Ok(())
+}
+
+fn info(rom_filename: &str, computer: Option<Computer>) -> Result<(), String> {
+    let (rom, detected, known) = read_rom(Path::new(rom_filename), computer)?;
+    let hash = movie::rom_hash_hex(&rom);
+    let how = match (computer, known.and_then(|entry| entry.computer)) {
+        (Some(_), _) => "picked",
+        (None, Some(_)) => "from the database",
+        (None, None) => "detected",
+    };
+    let quirks = known
+        .and_then(|entry| entry.quirks)
+        .unwrap_or_else(|| detected.default_quirks());
+    let on: Vec<&str> = [
+        (quirks.shift_uses_vy, "shift_uses_vy"),
+        (quirks.load_store_increments_i, "load_store_increments_i"),
+        (quirks.jump_uses_vx, "jump_uses_vx"),
+        (quirks.vf_reset, "vf_reset"),
+        (quirks.clip_sprites, "clip_sprites"),
+        (quirks.display_wait, "display_wait"),
+    ]
+    .iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, name)| *name)
+    .collect();
+
+    println!("file:     {rom_filename}");
+    println!("size:     {} bytes", rom.len());
+    println!("sha-1:    {hash}");
+    println!(
+        "computer: {} ({how}), loaded at 0x{:03X}",
+        Computer::NAMES[detected.id() as usize],
+        detected.program_start()
+    );
+    println!("quirks:   {}", on.join(", "));
+    let max = detected.ram_size() - detected.program_start();
+    if rom.len() > max {
+        println!("too large: only {max} bytes fit in memory");
+    }
+
+    let Some(entry) = known else {
+        println!("database: not known, running with the defaults");
+        return Ok(());
+    };
+    println!("title:    {}", entry.title);
+    if !entry.authors.is_empty() {
+        println!("authors:  {}", entry.authors.join(", "));
+    }
+    if let Some(release) = &entry.release {
+        println!("release:  {release}");
+    }
+    let platform = match (&entry.platform, entry.platforms.first()) {
+        (Some(platform), _) => platform.clone(),
+        (None, Some(platform)) => format!("{platform} (not emulated)"),
+        (None, None) => "not given".to_string(),
+    };
+    println!("platform: {platform}");
+    let ips = entry
+        .tickrate
+        .map_or(DEFAULT_INSTRUCTIONS_PER_SECOND, |tickrate| {
+            tickrate.saturating_mul(TIMER_FREQUENCY)
+        });
+    println!("speed:    {ips} instructions per second");
+    if !entry.colors.is_empty() {
+        let colors: Vec<String> = entry
+            .colors
+            .iter()
+            .map(|[r, g, b]| format!("#{r:02x}{g:02x}{b:02x}"))
+            .collect();
+        println!("colours:  {}", colors.join(", "));
+    }
+    if !entry.keys.is_empty() {
+        let keys: Vec<String> = entry
+            .keys
+            .iter()
+            .map(|(name, key)| format!("{name} {key:X}"))
+            .collect();
+        println!("keys:     {}", keys.join(", "));
+    }
+    if let Some(description) = &entry.description {
+        println!("\n{description}");
+    }
+    Ok(())
+}
+
+// a chip set up the way the options ask for, and what they leave out the way the settings say
+fn new_chip(
+    options: &RunOptions,
+    settings: RomSettings,
+    default_seed: impl FnOnce() -> u64,
+) -> Result<Chip8, String> {
+    let rom_filename = Path::new(options.rom.as_deref().unwrap_or_default());
+    let (rom, computer, _) = read_rom(rom_filename, options.computer.or(settings.computer))?;
+    let quirks = options
+        .quirks
+        .or(settings.quirks)
+        .unwrap_or_else(|| computer.default_quirks());
+    let ips = options
+        .ips
+        .or(settings.ips)
+        .unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND);
+    let speed = options.speed.or(settings.speed).unwrap_or(1);
+    let mut chip = Chip8::from_bytes(computer, quirks, &rom).map_err(|e| e.to_string())?;
+    chip.set_seed(options.seed.unwrap_or_else(default_seed));
+    chip.set_instructions_per_second(ips.saturating_mul(speed as u32));
+    Ok(chip)
+}
+
+// the hash of the rom's file and what the config, and under it the database, says about it
+fn rom_settings(options: &RunOptions, config: &Config) -> (String, RomSettings) {
+    let rom_filename = Path::new(options.rom.as_deref().unwrap_or_default());
+    let hash =
+        fs::read(rom_filename).map_or_else(|_| String::new(), |data| movie::rom_hash_hex(&data));
+    let settings = config.rom_settings(&hash);
+    (hash, settings)
+}
+
+fn run_headless(options: RunOptions, headless: HeadlessOptions) -> Result<i32, String> {
+    // runs are repeatable unless asked otherwise, and the same wherever they run, so only the
+    // database is used and not the user's config
+    let (_, settings) = rom_settings(&options, &Config::default());
+    let mut chip = new_chip(&options, settings, || DEFAULT_SEED)?;
+    let (frames, error) = match headless::run(&mut chip, headless.limit, &headless.keys) {
+        Ok(frames) => (frames, None),
+        Err((frames, e)) => (frames, Some(e)),
+    };
+
+    if headless.ascii.is_none() && headless.png.is_none() && headless.json.is_none() {
+        print!("{}", headless::display_to_ascii(&chip));
+    }
+    if let Some(out) = &headless.ascii {
+        write_output(out, headless::display_to_ascii(&chip).as_bytes())?;
+    }
+    if let Some(out) = &headless.png {
+        let scale = PNG_WIDTH / chip.display_width();
+        write_output(out, &headless::display_to_png(&chip, scale))?;
+    }
+    if let Some(out) = &headless.json {
+        write_output(
+            out,
+            headless::registers_json(&chip, frames, error.as_ref()).as_bytes(),
+        )?;
+    }
+    match error {
+        Some(e) => {
+            eprintln!("headless: {e}");
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+#[cfg(all(feature = "tty", not(target_arch = "wasm32")))]
+fn run_tty(options: RunOptions) -> Result<(), String> {
+    // like the window, every run is different unless a seed is given, and the rom's settings
+    // and keys come from the config
+    let config = Config::load();
+    let (hash, settings) = rom_settings(&options, &config);
+    let chip = new_chip(&options, settings, || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    })?;
+    let rom_filename = Path::new(options.rom.as_deref().unwrap_or_default());
+    let title = rom_filename.file_name().map_or_else(
+        || rom_filename.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    // the same keys as the window: the rom's bindings from the config and the database's arrow keys
+    let key_map = config.key_map(&hash);
+    // a --palette wins over the settings' one. the terminal has no rainbow mode
+    let color = match (options.rainbow, settings.palette) {
+        (None, Some((color, _))) => color,
+        _ => options.color,
+    };
+    crate::tty::run(chip, &title, &key_map, color, options.mute).map_err(|e| e.to_string())
+}
+
+#[cfg(not(all(feature = "tty", not(target_arch = "wasm32"))))]
+fn run_tty(_options: RunOptions) -> Result<(), String> {
+    Err("chippi was built without the tty feature".to_string())
+}
+
+// .8o files are compiled as octo, anything else is a rom. without a computer picked, the
+// database decides, or failing that the rom
+fn read_rom(
+    rom_filename: &Path,
+    computer: Option<Computer>,
+) -> Result<(Vec<u8>, Computer, Option<&'static Entry>), String> {
+    if rom_filename.extension().and_then(|e| e.to_str()) == Some("8o") {
+        let computer = computer.unwrap_or(Computer::Normal);
+        let rom = octo::compile_file(rom_filename, computer).map_err(|e| e.to_string())?;
+        return Ok((rom, computer, None));
+    }
+    let rom = fs::read(rom_filename)
+        .map_err(|e| format!("Couldn't read {}: {e}", rom_filename.display()))?;
+    let known = Database::bundled().lookup(&rom);
+    let computer = computer
+        .or(known.and_then(|entry| entry.computer))
+        .unwrap_or_else(|| Computer::detect(&rom_filename.to_string_lossy(), &rom));
+    Ok((rom, computer, known))
+}
+
+// - is stdout
+fn write_output(filename: &str, data: &[u8]) -> Result<(), String> {
+    if filename == "-" {
+        return std::io::stdout()
+            .write_all(data)
+            .map_err(|e| format!("Couldn't write to stdout: {e}"));
+    }
+    fs::write(filename, data).map_err(|e| format!("Couldn't write {filename}: {e}"))
+}
@@ -0,0 +1,272 @@
+// settings kept between runs, in config.toml in the user's config directory
+// (~/.config/chippi on linux). [defaults] applies to every rom, a [roms.<sha-1>] section to the
+// rom with that hash, wherever it's stored and whatever it's called:
+//
+//   [defaults]
+//   palette = "white"
+//
+//   [roms.607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee]
+//   name = "Pong (1 player).ch8"
+//   quirks = "vip"
+//   speed = 3
+//
+//   [roms.607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee.keys]
+//   1 = ["Up"]
+//   4 = ["Down"]
+//
+// the command line beats the rom's section, which beats what the rom database knows about the rom,
+// which beats the defaults. the hotkeys that change the speed and colour, and the keys overlay,
+// write them back to the rom's section. see keymap.rs for the keys
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use chippi::chip8::{Computer, TIMER_FREQUENCY};
+use chippi::database::{Database, Entry};
+use chippi::quirks::Quirks;
+
+use crate::cli::{MAX_SPEED, PALETTE_NAMES, PALETTE_RGB};
+use crate::keymap::KeyMap;
+
+const CONFIG_FILENAME: &str = "config.toml";
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Settings::is_empty")]
+    pub defaults: Settings,
+    // keyed by the lowercase hex sha-1 of the rom, see movie::rom_hash_hex
+    pub roms: BTreeMap<String, Settings>,
+    // set when the file is there but couldn't be loaded, so it isn't written over
+    #[serde(skip)]
+    read_only: bool,
+}
+
+// anything left out falls through to the next place a setting can come from
+#[derive(Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // the rom's file name when the section was written, only there to tell the sections apart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub computer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<usize>,
+    // one of PALETTE_NAMES, or rainbow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    // host key names by chip-8 key, replacing the layout for the chip-8 keys it names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<BTreeMap<String, Vec<String>>>,
+}
+
+// what a section says, checked. a value that doesn't make sense is reported and left out
+#[derive(Default, Clone, Copy)]
+pub struct RomSettings {
+    pub computer: Option<Computer>,
+    pub quirks: Option<Quirks>,
+    pub speed: Option<usize>,
+    // only ever from the database, the config has --speed for that
+    pub ips: Option<u32>,
+    // an index into PALETTE_NAMES and whether rainbow mode is on
+    pub palette: Option<(usize, bool)>,
+}
+
+impl RomSettings {
+    // what the database knows about a rom
+    pub fn known(entry: &Entry) -> RomSettings {
+        RomSettings {
+            computer: entry.computer,
+            quirks: entry.quirks,
+            speed: None,
+            ips: entry
+                .tickrate
+                .map(|tickrate| tickrate.saturating_mul(TIMER_FREQUENCY)),
+            palette: entry.colors.get(1).map(|rgb| (nearest_color(*rgb), false)),
+        }
+    }
+
+    // the settings left out here taken from below
+    fn over(self, below: RomSettings) -> RomSettings {
+        RomSettings {
+            computer: self.computer.or(below.computer),
+            quirks: self.quirks.or(below.quirks),
+            speed: self.speed.or(below.speed),
+            ips: self.ips.or(below.ips),
+            palette: self.palette.or(below.palette),
+        }
+    }
+}
+
+impl Settings {
+    fn is_empty(&self) -> bool {
+        *self == Settings::default()
+    }
+
+    fn check(&self, section: &str) -> RomSettings {
+        let warn = |key: &str, value: &dyn std::fmt::Display| {
+            eprintln!("chippi: ignoring {key} = {value} in [{section}] of {CONFIG_FILENAME}")
+        };
+        let computer = self.computer.as_deref().and_then(|name| {
+            Computer::from_name(name).or_else(|| {
+                warn("computer", &name);
+                None
+            })
+        });
+        let quirks = self.quirks.as_deref().and_then(|name| {
+            Quirks::from_name(name).or_else(|| {
+                warn("quirks", &name);
+                None
+            })
+        });
+        let speed = self.speed.filter(|speed| {
+            let ok = (1..=MAX_SPEED).contains(speed);
+            if !ok {
+                warn("speed", speed);
+            }
+            ok
+        });
+        let palette = self.palette.as_deref().and_then(|name| {
+            palette_from_name(name).or_else(|| {
+                warn("palette", &name);
+                None
+            })
+        });
+        RomSettings {
+            computer,
+            quirks,
+            speed,
+            ips: None,
+            palette,
+        }
+    }
+}
+
+impl Config {
+    // a missing file is an empty config. one that can't be loaded is reported, and left alone
+    pub fn load() -> Config {
+        let Some(path) = config_path() else {
+            return Config::default();
+        };
+        let result = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| toml::from_str(&text).map_err(|e| e.to_string()));
+        match result {
+            Ok(config) => config,
+            Err(_) if !path.exists() => Config::default(),
+            Err(e) => {
+                eprintln!("chippi: Couldn't load {}: {e}", path.display());
+                Config {
+                    read_only: true,
+                    ..Config::default()
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        // nowhere to keep it, e.g. in the browser
+        let Some(path) = config_path() else {
+            return Ok(());
+        };
+        if self.read_only {
+            return Err(format!(
+                "{} couldn't be loaded, fix it first",
+                path.display()
+            ));
+        }
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Couldn't create {}: {e}", dir.display()))?;
+        }
+        std::fs::write(&path, text).map_err(|e| format!("Couldn't write {}: {e}", path.display()))
+    }
+
+    // the rom's section on top of the database on top of the defaults
+    pub fn rom_settings(&self, hash: &str) -> RomSettings {
+        let mut settings = self.defaults.check("defaults");
+        if let Some(entry) = Database::bundled().lookup_hash(hash) {
+            settings = RomSettings::known(entry).over(settings);
+        }
+        if let Some(rom) = self.roms.get(hash) {
+            settings = rom.check(&format!("roms.{hash}")).over(settings);
+        }
+        settings
+    }
+
+    // the default layout with the database's arrow keys, the defaults' keys and the rom's keys.
+    // the arrow keys go first so a binding in the config always wins over them
+    pub fn key_map(&self, hash: &str) -> KeyMap {
+        let apply = |key_map: &mut KeyMap, settings: &Settings, section: &str| {
+            if let Some(keys) = &settings.keys {
+                key_map.apply(keys, |message| {
+                    eprintln!("chippi: {message} in [{section}] of {CONFIG_FILENAME}")
+                });
+            }
+        };
+        let mut key_map = KeyMap::default();
+        if let Some(entry) = Database::bundled().lookup_hash(hash) {
+            key_map.add_hints(&entry.keys);
+        }
+        apply(&mut key_map, &self.defaults, "defaults");
+        if let Some(rom) = self.roms.get(hash) {
+            apply(&mut key_map, rom, &format!("roms.{hash}"));
+        }
+        key_map
+    }
+
+    // the rom's section, made if there isn't one yet
+    pub fn rom_section(&mut self, hash: &str, name: &str) -> &mut Settings {
+        let section = self.roms.entry(hash.to_string()).or_default();
+        section.name = Some(name.to_string());
+        section
+    }
+}
+
+pub fn palette_name(color: usize, rainbow: bool) -> String {
+    match rainbow {
+        true => "rainbow".to_string(),
+        false => PALETTE_NAMES[color].to_string(),
+    }
+}
+
+// rainbow mode starts from the first colour
+pub fn palette_from_name(name: &str) -> Option<(usize, bool)> {
+    match name {
+        "rainbow" => Some((0, true)),
+        name => PALETTE_NAMES
+            .iter()
+            .position(|n| *n == name)
+            .map(|color| (color, false)),
+    }
+}
+
+// the index of the colour in PALETTE_NAMES closest to rgb
+pub fn nearest_color(rgb: [u8; 3]) -> usize {
+    let distance = |other: &[u8; 3]| -> u32 {
+        rgb.iter()
+            .zip(other)
+            .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+            .sum()
+    };
+    (0..PALETTE_RGB.len())
+        .min_by_key(|i| distance(&PALETTE_RGB[*i]))
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chippi").join(CONFIG_FILENAME))
+}
+
+// the browser has nowhere to keep a file
+#[cfg(target_arch = "wasm32")]
+fn config_path() -> Option<PathBuf> {
+    None
+}
@@ -0,0 +1,193 @@
+//! What's known about particular ROMs, looked up by the SHA-1 of the ROM.
+//!
+//! The format is the `programs.json` of the community
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database): a list of programs, each with the
+//! ROMs it was released as keyed by SHA-1. A copy covering the bundled ROMs is built in, see
+//! `database/README.md`. Fields chippi has no use for are ignored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::chip8::{Computer, ETI_PROGRAM_START_LOCATION};
+use crate::movie;
+use crate::quirks::Quirks;
+
+const BUNDLED: &str = include_str!("../database/programs.json");
+
+/// What the database says about one ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub description: Option<String>,
+    /// The platforms the ROM runs on as the database names them, e.g. `originalChip8`.
+    pub platforms: Vec<String>,
+    /// The first of the platforms chippi emulates, None when there's none.
+    pub platform: Option<String>,
+    pub computer: Option<Computer>,
+    /// The platform's quirks, with the ones the ROM needs different applied.
+    pub quirks: Option<Quirks>,
+    /// Instructions per 60hz frame.
+    pub tickrate: Option<u32>,
+    /// The background and then the colour of each plane.
+    pub colors: Vec<[u8; 3]>,
+    /// What the CHIP-8 keys do, e.g. `("left", 4)`.
+    pub keys: Vec<(String, u8)>,
+}
+
+pub struct Database {
+    // keyed by the lowercase hex sha-1, see movie::rom_hash_hex
+    roms: HashMap<String, Entry>,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    release: Option<String>,
+    description: Option<String>,
+    roms: BTreeMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    // platform id to the quirks that differ from the platform's
+    #[serde(default)]
+    quirky_platforms: BTreeMap<String, BTreeMap<String, bool>>,
+    tickrate: Option<u32>,
+    start_address: Option<u16>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+impl Database {
+    pub fn parse(json: &str) -> Result<Database, String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut roms = HashMap::new();
+        for program in programs {
+            for (hash, rom) in &program.roms {
+                roms.insert(hash.to_lowercase(), Database::entry(&program, rom)?);
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    /// The database built into chippi.
+    pub fn bundled() -> &'static Database {
+        static DATABASE: OnceLock<Database> = OnceLock::new();
+        DATABASE.get_or_init(|| Database::parse(BUNDLED).expect("the bundled database is valid"))
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Entry> {
+        self.lookup_hash(&movie::rom_hash_hex(rom))
+    }
+
+    pub fn lookup_hash(&self, hash: &str) -> Option<&Entry> {
+        self.roms.get(&hash.to_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    fn entry(program: &Program, rom: &Rom) -> Result<Entry, String> {
+        let supported = rom
+            .platforms
+            .iter()
+            .find_map(|id| platform(id).map(|platform| (id, platform)));
+        let mut computer = None;
+        let mut quirks = None;
+        if let Some((id, (platform_computer, mut platform_quirks))) = supported {
+            for (quirk, on) in rom.quirky_platforms.get(id).into_iter().flatten() {
+                apply_quirk(&mut platform_quirks, quirk, *on);
+            }
+            computer = Some(platform_computer);
+            quirks = Some(platform_quirks);
+        }
+        if rom.start_address == Some(ETI_PROGRAM_START_LOCATION as u16) {
+            computer = Some(Computer::Eti);
+        }
+
+        let colors = rom.colors.as_ref().map_or(&[][..], |c| &c.pixels);
+        let colors = colors
+            .iter()
+            .map(|color| {
+                parse_color(color)
+                    .ok_or_else(|| format!("{}: colour {color} not valid", program.title))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Entry {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            release: program.release.clone(),
+            description: program.description.clone(),
+            platforms: rom.platforms.clone(),
+            platform: supported.map(|(id, _)| id.clone()),
+            computer,
+            quirks,
+            tickrate: rom.tickrate,
+            colors,
+            keys: rom
+                .keys
+                .iter()
+                .map(|(name, key)| (name.clone(), *key))
+                .collect(),
+        })
+    }
+}
+
+/// The computer and quirks of a platform of the database, None for the ones chippi doesn't
+/// emulate (CHIP-8X and MegaChip).
+pub fn platform(id: &str) -> Option<(Computer, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Computer::Normal, Quirks::COSMAC_VIP)),
+        "modernChip8" => Some((Computer::Normal, Quirks::MODERN)),
+        "chip48" => Some((Computer::Normal, Quirks::CHIP48)),
+        "superchip1" | "superchip" => Some((Computer::SuperChip, Quirks::SUPER_CHIP)),
+        "xochip" => Some((Computer::XoChip, Quirks::MODERN)),
+        _ => None,
+    }
+}
+
+// the database names a quirk for what it does when on, which isn't always what Quirks calls on.
+// memoryIncrementByX has no counterpart here and is left alone
+fn apply_quirk(quirks: &mut Quirks, name: &str, on: bool) {
+    match name {
+        "shift" => quirks.shift_uses_vy = !on,
+        "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !on,
+        "jump" => quirks.jump_uses_vx = on,
+        "logic" => quirks.vf_reset = on,
+        "wrap" => quirks.clip_sprites = !on,
+        "vblank" => quirks.display_wait = on,
+        _ => {}
+    }
+}
+
+// #rrggbb
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
@@ -0,0 +1,718 @@
+//! Execution control for stepping through a program: breakpoints, watchpoints, conditions,
+//! step, step over and run to return.
+//!
+//! The debugger never runs the chip itself while continuing. The front-end keeps running frames
+//! with `Chip8::run_frame_until(|chip| debugger.should_break(chip))` and stops once it says so.
+//!
+//! Conditions are comparisons between registers (`V0`-`VF`, `I`, `PC`, `SP`, `DT`, `ST`), numbers
+//! and bytes of memory (`[I]`, `[0x300]`), joined with `&&`, `||` and parentheses:
+//! `V3 == 0x10 && I > 0x300`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::chip8::{Chip8, Chip8Error, MemoryAccess, StepOutcome};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Paused,
+    Running,
+    // a CALL is being stepped over, stop once it returns to the instruction after it
+    StepOver { return_pc: u16, sp: u8 },
+    // stop once the current subroutine has returned
+    RunToReturn { sp: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // a write that stores a different value than was there
+    Change,
+}
+
+impl WatchKind {
+    pub const NAMES: [&'static str; 3] = ["read", "write", "change"];
+
+    pub fn from_name(name: &str) -> Option<WatchKind> {
+        match name.to_lowercase().as_str() {
+            "r" | "read" => Some(WatchKind::Read),
+            "w" | "write" => Some(WatchKind::Write),
+            "c" | "change" => Some(WatchKind::Change),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+        }
+    }
+
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        match (self, access) {
+            (WatchKind::Read, MemoryAccess::Read { .. }) => true,
+            (WatchKind::Write, MemoryAccess::Write { .. }) => true,
+            (WatchKind::Change, MemoryAccess::Write { old, value, .. }) => old != value,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub kind: WatchKind,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // each with whether it held before the last instruction. they break when they become true,
+    // not for as long as they stay true
+    conditions: Vec<(Condition, bool)>,
+    state: RunState,
+    // the breakpoint we were stopped on, skipped once so continuing doesn't stop right away
+    resume_pc: Option<u16>,
+    reason: Option<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+            state: RunState::Paused,
+            resume_pc: None,
+            reason: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    /// Why execution last stopped, when it was a breakpoint, watchpoint or condition.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn resume(&mut self, chip: &Chip8) {
+        self.run(RunState::Running, chip);
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = &Condition> {
+        self.conditions.iter().map(|(condition, _)| condition)
+    }
+
+    /// Breaks whenever `condition` goes from false to true.
+    pub fn add_condition(&mut self, condition: Condition, chip: &Chip8) {
+        let holds = condition.eval(chip);
+        self.conditions.push((condition, holds));
+    }
+
+    /// Removes every breakpoint, watchpoint and condition.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.conditions.clear();
+    }
+
+    /// Runs a command typed into the debugger, returning what to tell the user.
+    ///
+    /// - `break <addr>` toggles a breakpoint, `break <condition>` adds a condition
+    /// - `watch <addr> [read|write|change]` adds a watchpoint, on changes if no kind is given
+    /// - `clear` removes all of them
+    pub fn command(&mut self, line: &str, chip: &Chip8) -> Result<String, String> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            "break" | "b" => {
+                if let Some(addr) = parse_number(args) {
+                    self.toggle_breakpoint(addr);
+                    return Ok(match self.breakpoints.contains(&addr) {
+                        true => format!("Breakpoint at {addr:04X}"),
+                        false => format!("Removed breakpoint at {addr:04X}"),
+                    });
+                }
+                let condition = Condition::parse(args).map_err(|e| e.to_string())?;
+                let message = format!("Break when {condition}");
+                self.add_condition(condition, chip);
+                Ok(message)
+            }
+            "watch" | "w" => {
+                let mut words = args.split_whitespace();
+                let addr = words
+                    .next()
+                    .and_then(parse_number)
+                    .ok_or("watch needs an address")?;
+                let kind = match words.next() {
+                    Some(kind) => WatchKind::from_name(kind).ok_or_else(|| {
+                        format!(
+                            "Unknown watch kind {kind}, expected one of {:?}",
+                            WatchKind::NAMES
+                        )
+                    })?,
+                    None => WatchKind::Change,
+                };
+                self.add_watchpoint(Watchpoint { addr, kind });
+                Ok(format!("Watching {} of {addr:04X}", kind.name()))
+            }
+            "clear" => {
+                self.clear();
+                Ok("Cleared all breakpoints".to_string())
+            }
+            _ => Err(format!(
+                "Unknown command {name}, expected break, watch or clear"
+            )),
+        }
+    }
+
+    /// Executes exactly one instruction.
+    pub fn step(&mut self, chip: &mut Chip8) -> Result<StepOutcome, Chip8Error> {
+        self.state = RunState::Paused;
+        self.reason = None;
+        let outcome = chip.tick()?;
+        // keep the conditions in step with what the program did, without stopping on them
+        for (condition, held) in &mut self.conditions {
+            *held = condition.eval(chip);
+        }
+        Ok(outcome)
+    }
+
+    /// Like `step`, but a CALL runs until its subroutine has returned.
+    pub fn step_over(&mut self, chip: &mut Chip8) -> Result<StepOutcome, Chip8Error> {
+        let pc = chip.pc();
+        let is_call = chip.ram.get(pc as usize).is_some_and(|b| b >> 4 == 0x2);
+        if !is_call {
+            return self.step(chip);
+        }
+        let state = RunState::StepOver {
+            return_pc: pc.wrapping_add(2),
+            sp: chip.sp(),
+        };
+        self.run(state, chip);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Runs until the subroutine the program is in returns to its caller. Outside of a subroutine
+    /// there's nothing to return from, so it stays paused.
+    pub fn run_to_return(&mut self, chip: &Chip8) {
+        if chip.sp() == 0 {
+            self.state = RunState::Paused;
+            self.reason = Some("Not in a subroutine".to_string());
+            return;
+        }
+        self.run(RunState::RunToReturn { sp: chip.sp() }, chip);
+    }
+
+    fn run(&mut self, state: RunState, chip: &Chip8) {
+        self.state = state;
+        self.resume_pc = Some(chip.pc());
+        self.reason = None;
+    }
+
+    /// Checked before every instruction while running. Pauses and returns true when execution has to stop.
+    /// Watchpoints see the accesses of the instruction that just ran, so execution stops right after it.
+    pub fn should_break(&mut self, chip: &Chip8) -> bool {
+        let pc = chip.pc();
+        // conditions are evaluated on every instruction, even skipped ones, so they only fire on changes
+        let mut became_true = None;
+        for (condition, held) in &mut self.conditions {
+            let holds = condition.eval(chip);
+            if holds && !*held && became_true.is_none() {
+                became_true = Some(format!("{condition}"));
+            }
+            *held = holds;
+        }
+        if self.resume_pc.take() == Some(pc) {
+            return false;
+        }
+
+        let watched = chip.memory_accesses().iter().find(|access| {
+            self.watchpoints
+                .iter()
+                .any(|w| w.addr == access.addr() && w.kind.matches(access))
+        });
+        let reason = if let Some(access) = watched {
+            Some(match *access {
+                MemoryAccess::Read { addr, value } => format!("Read {addr:04X}: {value:02X}"),
+                MemoryAccess::Write { addr, old, value } => {
+                    format!("Write {addr:04X}: {old:02X} -> {value:02X}")
+                }
+            })
+        } else if self.breakpoints.contains(&pc) {
+            Some(format!("Breakpoint at {pc:04X}"))
+        } else {
+            became_true
+        };
+        let stop = match self.state {
+            RunState::Paused => true,
+            RunState::Running => false,
+            RunState::StepOver { return_pc, sp } => pc == return_pc && chip.sp() == sp,
+            RunState::RunToReturn { sp } => chip.sp() < sp,
+        };
+        if stop || reason.is_some() {
+            self.state = RunState::Paused;
+            self.reason = reason;
+            return true;
+        }
+        false
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+/// Accepts `0x` hex, `0b` binary and decimal.
+pub fn parse_number(text: &str) -> Option<u16> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column + 1)
+    }
+}
+
+impl std::error::Error for ConditionError {}
+
+/// A parsed breakpoint condition. Displays as the text it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(Value, Comparison, Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(u16),
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    // the byte of ram at an address
+    Memory(Box<Value>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(&'static str),
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, ConditionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.len(),
+        };
+        let expr = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error("expected && or ||"));
+        }
+        Ok(Condition {
+            source: source.trim().to_string(),
+            expr,
+        })
+    }
+
+    pub fn eval(&self, chip: &Chip8) -> bool {
+        self.expr.eval(chip)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Expr {
+    fn eval(&self, chip: &Chip8) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(chip) || b.eval(chip),
+            Expr::And(a, b) => a.eval(chip) && b.eval(chip),
+            Expr::Compare(a, op, b) => {
+                let (a, b) = (a.eval(chip), b.eval(chip));
+                match op {
+                    Comparison::Eq => a == b,
+                    Comparison::Ne => a != b,
+                    Comparison::Lt => a < b,
+                    Comparison::Le => a <= b,
+                    Comparison::Gt => a > b,
+                    Comparison::Ge => a >= b,
+                }
+            }
+        }
+    }
+}
+
+impl Value {
+    fn eval(&self, chip: &Chip8) -> u16 {
+        match self {
+            Value::Number(n) => *n,
+            Value::V(x) => chip.regs()[*x] as u16,
+            Value::I => chip.ireg(),
+            Value::Pc => chip.pc(),
+            Value::Sp => chip.sp() as u16,
+            Value::Dt => chip.dreg() as u16,
+            Value::St => chip.sreg as u16,
+            // out of range reads as 0 rather than failing the whole condition
+            Value::Memory(addr) => {
+                chip.ram.get(addr.eval(chip) as usize).copied().unwrap_or(0) as u16
+            }
+        }
+    }
+
+    fn from_word(word: &str) -> Option<Value> {
+        let upper = word.to_uppercase();
+        let value = match upper.as_str() {
+            "I" => Value::I,
+            "PC" => Value::Pc,
+            "SP" => Value::Sp,
+            "DT" => Value::Dt,
+            "ST" => Value::St,
+            _ => match upper.strip_prefix('V') {
+                Some(x) if x.len() == 1 => Value::V(usize::from_str_radix(x, 16).ok()?),
+                _ => Value::Number(parse_number(word)?),
+            },
+        };
+        Some(value)
+    }
+}
+
+// longest first, so <= isn't read as < followed by =
+const OPERATORS: [&str; 10] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "(", ")"];
+const BRACKETS: [&str; 2] = ["[", "]"];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ConditionError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < source.len() {
+        let rest = &source[pos..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if let Some(op) = OPERATORS
+            .iter()
+            .chain(&BRACKETS)
+            .find(|op| rest.starts_with(**op))
+        {
+            tokens.push((pos, Token::Op(op)));
+            pos += op.len();
+        } else if c.is_ascii_alphanumeric() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push((pos, Token::Word(rest[..len].to_string())));
+            pos += len;
+        } else {
+            return Err(ConditionError {
+                column: pos,
+                message: "unexpected character",
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // column reported for errors past the last token
+    end: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &'static str) -> ConditionError {
+        let column = self
+            .tokens
+            .get(self.pos)
+            .map_or(self.end, |(column, _)| *column);
+        ConditionError { column, message }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some((_, Token::Op(o))) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ConditionError> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ConditionError> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error("expected )"));
+            }
+            return Ok(expr);
+        }
+        let left = self.value()?;
+        let op = match self.tokens.get(self.pos) {
+            Some((_, Token::Op(op))) => match *op {
+                "==" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                ">=" => Comparison::Ge,
+                _ => return Err(self.error("expected a comparison")),
+            },
+            _ => return Err(self.error("expected a comparison")),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(left, op, self.value()?))
+    }
+
+    fn value(&mut self) -> Result<Value, ConditionError> {
+        if self.eat("[") {
+            let addr = self.value()?;
+            if !self.eat("]") {
+                return Err(self.error("expected ]"));
+            }
+            return Ok(Value::Memory(Box::new(addr)));
+        }
+        let value = match self.tokens.get(self.pos) {
+            Some((_, Token::Word(word))) => Value::from_word(word),
+            _ => None,
+        };
+        let value = value.ok_or_else(|| self.error("expected a register or number"))?;
+        self.pos += 1;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Computer;
+    use crate::quirks::Quirks;
+
+    // a chip with `program` at 0x200 that has run its first `steps` instructions
+    fn chip(program: &[u16], steps: usize) -> Chip8 {
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &rom).unwrap();
+        for _ in 0..steps {
+            chip.tick().unwrap();
+        }
+        chip
+    }
+
+    fn holds(condition: &str, chip: &Chip8) -> bool {
+        Condition::parse(condition).unwrap().eval(chip)
+    }
+
+    // where a running debugger stopped, and why, until the program reaches its last instruction
+    fn stops(program: &[u16], debugger: &mut Debugger) -> Vec<(u16, String)> {
+        let mut chip = chip(program, 0);
+        let end = 0x200 + 2 * (program.len() as u16 - 1);
+        let mut stops = Vec::new();
+        debugger.resume(&chip);
+        loop {
+            if debugger.should_break(&chip) {
+                stops.push((chip.pc(), debugger.reason().unwrap_or_default().to_string()));
+                debugger.resume(&chip);
+                continue;
+            }
+            if chip.pc() == end {
+                return stops;
+            }
+            chip.tick().unwrap();
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        // V0 = 1, V1 = 0, V2 = 0
+        let chip = chip(&[0x6001], 1);
+        assert!(holds("V0 == 1 || V1 == 1 && V2 == 1", &chip));
+        assert!(!holds("(V0 == 1 || V1 == 1) && V2 == 1", &chip));
+        assert!(holds("V2 == 1 && V1 == 1 || V0 == 1", &chip));
+        assert!(holds("((V0 != 0))", &chip));
+    }
+
+    #[test]
+    fn registers_and_numbers() {
+        // I = 0x300, V3 = 0x10
+        let chip = chip(&[0xA300, 0x6310], 2);
+        assert!(holds("V3 == 0x10 && I > 0x2FF", &chip));
+        assert!(holds("v3 >= 16 && i <= 0b1100000000", &chip));
+        assert!(holds("PC == 0x204 && SP == 0 && DT == 0 && ST == 0", &chip));
+        assert!(!holds("V3 < 0x10", &chip));
+    }
+
+    #[test]
+    fn memory_reads() {
+        let mut chip = chip(&[0xA300], 1);
+        chip.ram[0x300] = 0x42;
+        assert!(holds("[I] == 0x42", &chip));
+        assert!(holds("[0x300] == 66", &chip));
+        assert!(holds("[0x301] == 0", &chip));
+        // past the end of ram
+        assert!(holds("[0xFFFF] == 0", &chip));
+    }
+
+    #[test]
+    fn errors_point_at_the_column() {
+        let error = |source| Condition::parse(source).unwrap_err();
+        assert_eq!(
+            error("V3 =="),
+            ConditionError {
+                column: 5,
+                message: "expected a register or number"
+            }
+        );
+        assert_eq!(
+            error("V3 == 0x10 &&"),
+            ConditionError {
+                column: 13,
+                message: "expected a register or number"
+            }
+        );
+        assert_eq!(
+            error("V3 == 0x10 &&").to_string(),
+            "expected a register or number at column 14"
+        );
+        assert_eq!(error("V3 0x10").column, 3);
+        assert_eq!(error("(V3 == 1").message, "expected )");
+        assert_eq!(error("[I == 1").message, "expected ]");
+        assert_eq!(error("V3 == 1 V4").message, "expected && or ||");
+        assert_eq!(error("V3 == $").column, 6);
+    }
+
+    #[test]
+    fn conditions_break_when_they_become_true() {
+        // V0 = 1, 2, 2, 3, 2 and then spins
+        let program = [0x6001, 0x6002, 0x6002, 0x6003, 0x6002, 0x120A];
+        let mut debugger = Debugger::new();
+        let condition = Condition::parse("V0 == 2").unwrap();
+        debugger.add_condition(condition, &chip(&program, 0));
+        let stops = stops(&program, &mut debugger);
+        assert_eq!(
+            stops,
+            vec![
+                (0x204, "V0 == 2".to_string()),
+                (0x20A, "V0 == 2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        // I = 0x300, V0 = 123, LD B, V0 twice, then LD V0, [I]
+        let program = [0xA300, 0x607B, 0xF033, 0xF033, 0xF065, 0x120A];
+        let watch = |addr, kind| {
+            let mut debugger = Debugger::new();
+            debugger.add_watchpoint(Watchpoint { addr, kind });
+            stops(&program, &mut debugger)
+        };
+        assert_eq!(
+            watch(0x301, WatchKind::Write),
+            vec![
+                (0x206, "Write 0301: 00 -> 02".to_string()),
+                (0x208, "Write 0301: 02 -> 02".to_string())
+            ]
+        );
+        assert_eq!(
+            watch(0x301, WatchKind::Change),
+            vec![(0x206, "Write 0301: 00 -> 02".to_string())]
+        );
+        assert_eq!(
+            watch(0x300, WatchKind::Read),
+            vec![(0x20A, "Read 0300: 01".to_string())]
+        );
+        assert_eq!(watch(0x303, WatchKind::Write), vec![]);
+    }
+
+    #[test]
+    fn run_to_return_needs_a_subroutine() {
+        let chip = chip(&[0x1200], 0);
+        let mut debugger = Debugger::new();
+        debugger.run_to_return(&chip);
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.reason(), Some("Not in a subroutine"));
+    }
+}
@@ -0,0 +1,358 @@
+//! Turns instructions back into the mnemonics used in the doc comments of `chip8.rs`.
+//!
+//! `disassemble_rom` produces a listing of a whole ROM. Code is told apart from data by following
+//! every path the program can take from its first instruction; whatever is never reached comes out
+//! as `db` bytes. The listing is valid assembler source that assembles back into the same ROM.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::chip8::Computer;
+
+// data bytes per db line
+const DATA_BYTES_PER_LINE: usize = 8;
+// column the address comments line up on
+const COMMENT_COLUMN: usize = 28;
+
+/// Disassembles the instruction at `addr`, returning its text and its length in bytes.
+/// Words that aren't instructions on `computer` come out as `DW` data.
+pub fn disassemble(ram: &[u8], addr: usize, computer: Computer) -> (String, usize) {
+    let byte = |offset: usize| ram.get(addr + offset).copied().unwrap_or(0) as u16;
+    let opcode = byte(0) << 8 | byte(1);
+
+    let xo = computer == Computer::XoChip;
+    if xo && opcode == 0xF000 {
+        return (format!("LD I, long 0x{:04X}", byte(2) << 8 | byte(3)), 4);
+    }
+    let text = mnemonic(opcode, computer).unwrap_or_else(|| format!("DW 0x{opcode:04X}"));
+    (text, 2)
+}
+
+/// The mnemonic for a two byte opcode, if it is an instruction on `computer`.
+/// F000, the first half of the XO-Chip long load, is not; use `disassemble` for it.
+pub fn mnemonic(opcode: u16, computer: Computer) -> Option<String> {
+    let xo = computer == Computer::XoChip;
+    let schip = xo || computer == Computer::SuperChip;
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    let text = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            0x00C0..=0x00CF if schip => format!("SCD {n}"),
+            0x00D0..=0x00DF if xo => format!("SCU {n}"),
+            0x00FB if schip => "SCR".to_string(),
+            0x00FC if schip => "SCL".to_string(),
+            0x00FD if schip => "EXIT".to_string(),
+            0x00FE if schip => "LOW".to_string(),
+            0x00FF if schip => "HIGH".to_string(),
+            _ => format!("SYS 0x{nnn:03X}"),
+        },
+        0x1 => format!("JP 0x{nnn:03X}"),
+        0x2 => format!("CALL 0x{nnn:03X}"),
+        0x3 => format!("SE V{x:X}, 0x{kk:02X}"),
+        0x4 => format!("SNE V{x:X}, 0x{kk:02X}"),
+        0x5 => match n {
+            0x0 => format!("SE V{x:X}, V{y:X}"),
+            0x2 if xo => format!("SAVE V{x:X}, V{y:X}"),
+            0x3 if xo => format!("LOAD V{x:X}, V{y:X}"),
+            _ => return None,
+        },
+        0x6 => format!("LD V{x:X}, 0x{kk:02X}"),
+        0x7 => format!("ADD V{x:X}, 0x{kk:02X}"),
+        0x8 => {
+            let op = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return None,
+            };
+            format!("{op} V{x:X}, V{y:X}")
+        }
+        0x9 if n == 0 => format!("SNE V{x:X}, V{y:X}"),
+        0xA => format!("LD I, 0x{nnn:03X}"),
+        0xB => format!("JP V0, 0x{nnn:03X}"),
+        0xC => format!("RND V{x:X}, 0x{kk:02X}"),
+        0xD => format!("DRW V{x:X}, V{y:X}, {n}"),
+        0xE => match kk {
+            0x9E => format!("SKP V{x:X}"),
+            0xA1 => format!("SKNP V{x:X}"),
+            _ => return None,
+        },
+        0xF => match kk {
+            0x01 if xo => format!("PLANE {x}"),
+            0x02 if xo && x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{x:X}, DT"),
+            0x0A => format!("LD V{x:X}, K"),
+            0x15 => format!("LD DT, V{x:X}"),
+            0x18 => format!("LD ST, V{x:X}"),
+            0x1E => format!("ADD I, V{x:X}"),
+            0x29 => format!("LD F, V{x:X}"),
+            0x30 if schip => format!("LD HF, V{x:X}"),
+            0x33 => format!("LD B, V{x:X}"),
+            0x3A if xo => format!("PITCH V{x:X}"),
+            0x55 => format!("LD [I], V{x:X}"),
+            0x65 => format!("LD V{x:X}, [I]"),
+            0x75 if schip => format!("LD R, V{x:X}"),
+            0x85 if schip => format!("LD V{x:X}, R"),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Disassembles a ROM loaded where `computer` loads it (0x200, or 0x600 on the ETI 660) into a
+/// listing with labels for JP and CALL targets.
+pub fn disassemble_rom(rom: &[u8], computer: Computer) -> String {
+    let origin = computer.program_start();
+    // the ram the rom would be loaded into, so addresses line up with what the interpreter sees
+    let mut ram = vec![0; origin];
+    ram.extend_from_slice(rom);
+    let end = ram.len();
+
+    let code = trace(&ram, origin, computer);
+
+    // lay out instructions and data first: a jump can only get a label if it lands where a line starts
+    let mut lines = Vec::new();
+    let mut addr = origin;
+    while addr < end {
+        let len = if code.contains(&addr) {
+            disassemble(&ram, addr, computer).1
+        } else {
+            0
+        };
+        if len > 0 && addr + len <= end {
+            lines.push((addr, len, true));
+            addr += len;
+        } else {
+            // data runs up to the next instruction, capped at one line
+            let len = (addr + 1..end)
+                .take(DATA_BYTES_PER_LINE - 1)
+                .take_while(|a| !code.contains(a))
+                .count()
+                + 1;
+            lines.push((addr, len, false));
+            addr += len;
+        }
+    }
+    let starts: BTreeSet<usize> = lines.iter().map(|(addr, _, _)| *addr).collect();
+
+    let mut labels = BTreeMap::new();
+    for (addr, _, _) in lines.iter().filter(|(_, _, is_code)| *is_code) {
+        let opcode = (ram[*addr] as u16) << 8 | ram[*addr + 1] as u16;
+        let target = (opcode & 0xFFF) as usize;
+        if !starts.contains(&target) {
+            continue;
+        }
+        match opcode >> 12 {
+            0x2 => {
+                labels.insert(target, format!("sub_{target:03X}"));
+            }
+            0x1 => {
+                labels.entry(target).or_insert(format!("loc_{target:03X}"));
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "; {} bytes, disassembled for {}",
+        rom.len(),
+        Computer::NAMES[computer.id() as usize]
+    );
+    for (addr, len, is_code) in lines {
+        if let Some(label) = labels.get(&addr) {
+            let _ = writeln!(out, "\n{label}:");
+        }
+        let bytes = &ram[addr..addr + len];
+        let text = if is_code {
+            let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+            let label = labels.get(&((opcode & 0xFFF) as usize));
+            match (opcode >> 12, label) {
+                (0x1, Some(label)) => format!("JP {label}"),
+                (0x2, Some(label)) => format!("CALL {label}"),
+                _ => disassemble(&ram, addr, computer).0,
+            }
+        } else {
+            let data: Vec<String> = bytes.iter().map(|b| format!("0x{b:02X}")).collect();
+            format!("db {}", data.join(", "))
+        };
+        // data already shows its bytes
+        let raw: String = match is_code {
+            true => bytes
+                .iter()
+                .fold("  ".to_string(), |raw, b| raw + &format!("{b:02X}")),
+            false => String::new(),
+        };
+        let _ = writeln!(
+            out,
+            "    {text:<width$} ; {addr:03X}{raw}",
+            width = COMMENT_COLUMN - 5
+        );
+    }
+    out
+}
+
+// the addresses of every instruction reachable from `start`
+fn trace(ram: &[u8], start: usize, computer: Computer) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut pending = vec![start];
+    while let Some(addr) = pending.pop() {
+        if addr + 1 >= ram.len() || addr < start || !code.insert(addr) {
+            continue;
+        }
+        let (text, len) = disassemble(ram, addr, computer);
+        let opcode = (ram[addr] as u16) << 8 | ram[addr + 1] as u16;
+        let nnn = (opcode & 0xFFF) as usize;
+        let next = addr + len;
+        // a skipped instruction may itself be a 4 byte long load
+        let after_next = next + disassemble(ram, next, computer).1;
+        match opcode >> 12 {
+            _ if text.starts_with("DW") => {
+                code.remove(&addr);
+            }
+            0x0 if opcode == 0x00EE || text == "EXIT" => {}
+            0x1 => pending.push(nnn),
+            0x2 => pending.extend([nnn, next]),
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => pending.extend([next, after_next]),
+            // a jump table usually starts at nnn, the offset in V0 picks an entry
+            0xB => pending.push(nnn),
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::asm;
+
+    const COMPUTERS: [Computer; 4] = [
+        Computer::Normal,
+        Computer::Eti,
+        Computer::SuperChip,
+        Computer::XoChip,
+    ];
+
+    #[test]
+    fn bundled_roms_assemble_back() {
+        let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        let mut paths: Vec<_> = fs::read_dir(&roms)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|e| e == "ch8"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let rom = fs::read(&path).unwrap();
+            for computer in COMPUTERS {
+                let listing = disassemble_rom(&rom, computer);
+                let assembled = asm::assemble(&listing, computer)
+                    .unwrap_or_else(|e| panic!("{} on {computer:?}: {e}", path.display()));
+                assert!(
+                    assembled == rom,
+                    "{} on {computer:?} doesn't assemble back",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn super_chip_instructions() {
+        let cases = [
+            (0x00C4, "SCD 4"),
+            (0x00FB, "SCR"),
+            (0x00FC, "SCL"),
+            (0x00FD, "EXIT"),
+            (0x00FE, "LOW"),
+            (0x00FF, "HIGH"),
+            (0xF330, "LD HF, V3"),
+            (0xF575, "LD R, V5"),
+            (0xF585, "LD V5, R"),
+        ];
+        for (opcode, expected) in cases {
+            for computer in [Computer::SuperChip, Computer::XoChip] {
+                assert_eq!(mnemonic(opcode, computer).as_deref(), Some(expected));
+            }
+        }
+        // the 00Cn..00FF range is SYS on the plain chip-8, the Fx ones aren't instructions at all
+        assert_eq!(
+            mnemonic(0x00FF, Computer::Normal).as_deref(),
+            Some("SYS 0x0FF")
+        );
+        assert_eq!(
+            mnemonic(0x00C4, Computer::Eti).as_deref(),
+            Some("SYS 0x0C4")
+        );
+        assert_eq!(mnemonic(0xF330, Computer::Normal), None);
+        assert_eq!(mnemonic(0xF575, Computer::Eti), None);
+    }
+
+    #[test]
+    fn xo_chip_instructions() {
+        let cases = [
+            (0x00D2, "SCU 2"),
+            (0x5122, "SAVE V1, V2"),
+            (0x5123, "LOAD V1, V2"),
+            (0xF201, "PLANE 2"),
+            (0xF002, "AUDIO"),
+            (0xF43A, "PITCH V4"),
+        ];
+        for (opcode, expected) in cases {
+            assert_eq!(
+                mnemonic(opcode, Computer::XoChip).as_deref(),
+                Some(expected)
+            );
+        }
+        assert_eq!(
+            mnemonic(0x00D2, Computer::SuperChip).as_deref(),
+            Some("SYS 0x0D2")
+        );
+        for computer in [Computer::Normal, Computer::SuperChip] {
+            assert_eq!(mnemonic(0x5122, computer), None);
+            assert_eq!(mnemonic(0xF201, computer), None);
+            assert_eq!(mnemonic(0xF43A, computer), None);
+        }
+        // AUDIO has no register
+        assert_eq!(mnemonic(0xF102, Computer::XoChip), None);
+    }
+
+    #[test]
+    fn unknown_words_are_data() {
+        assert_eq!(
+            disassemble(&[0xF2, 0x01], 0, Computer::SuperChip),
+            ("DW 0xF201".to_string(), 2)
+        );
+        assert_eq!(
+            disassemble(&[0x81, 0x28], 0, Computer::Normal),
+            ("DW 0x8128".to_string(), 2)
+        );
+        assert_eq!(
+            disassemble(&[0xF0, 0x00, 0x12, 0x34], 0, Computer::XoChip),
+            ("LD I, long 0x1234".to_string(), 4)
+        );
+        assert_eq!(
+            disassemble(&[0xF0, 0x00, 0x12, 0x34], 0, Computer::SuperChip),
+            ("DW 0xF000".to_string(), 2)
+        );
+    }
+}
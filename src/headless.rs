@@ -0,0 +1,246 @@
+//! Running a program with no window or audio, and dumping what it did.
+//!
+//! Keys are scripted as `<frame>[-<frame>]:<keys>` entries separated by commas. `60-65:5,120:AB`
+//! holds key 5 from frame 60 through 65 and keys A and B on frame 120.
+
+use std::fmt::Write;
+
+use crate::chip8::{Chip8, Chip8Error, StepOutcome, TIMER_FREQUENCY};
+
+// background, plane 1, plane 2, both planes
+const PNG_PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 85, 85], [255, 255, 85]];
+const ASCII_PALETTE: [char; 4] = ['.', '#', '+', '%'];
+
+/// How long to run for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Frames(u64),
+    Instructions(u64),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    // first frame, last frame, keyboard bits
+    presses: Vec<(u64, u64, u16)>,
+}
+
+impl KeyScript {
+    pub fn parse(script: &str) -> Result<KeyScript, String> {
+        let mut presses = Vec::new();
+        for entry in script.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid =
+                || format!("Key press {entry} not valid, expected <frame>[-<frame>]:<keys>");
+            let (frames, keys) = entry.split_once(':').ok_or_else(invalid)?;
+            let (first, last) = frames.split_once('-').unwrap_or((frames, frames));
+            let first: u64 = first.trim().parse().map_err(|_| invalid())?;
+            let last: u64 = last.trim().parse().map_err(|_| invalid())?;
+            if last < first {
+                return Err(format!(
+                    "Key press {entry} not valid, frame {last} comes before {first}"
+                ));
+            }
+            let mut bits = 0;
+            for key in keys.trim().chars() {
+                bits |= 1 << key.to_digit(16).ok_or_else(invalid)?;
+            }
+            presses.push((first, last, bits));
+        }
+        Ok(KeyScript { presses })
+    }
+
+    pub fn extend(&mut self, other: KeyScript) {
+        self.presses.extend(other.presses);
+    }
+
+    /// The last frame a key is pressed on.
+    pub fn last_frame(&self) -> Option<u64> {
+        self.presses.iter().map(|(_, last, _)| *last).max()
+    }
+
+    /// The keyboard during `frame`.
+    pub fn keys(&self, frame: u64) -> [u8; 16] {
+        let bits = self
+            .presses
+            .iter()
+            .filter(|(first, last, _)| (*first..=*last).contains(&frame))
+            .fold(0, |bits, (_, _, keys)| bits | keys);
+        crate::movie::bits_to_keys(bits)
+    }
+}
+
+/// Runs `chip` until the limit is reached or the program exits. Returns the frames run, which
+/// come with the error too when the program fails, counting the frame it failed in.
+pub fn run(chip: &mut Chip8, limit: Limit, keys: &KeyScript) -> Result<u64, (u64, Chip8Error)> {
+    match limit {
+        Limit::Frames(frames) => {
+            for frame in 0..frames {
+                chip.kb = keys.keys(frame);
+                chip.run_frame().map_err(|e| (frame + 1, e))?;
+                if chip.has_exited() {
+                    return Ok(frame + 1);
+                }
+            }
+            Ok(frames)
+        }
+        // same pacing as run_frame, counting the instructions that ran instead of frames. waiting
+        // for a key doesn't count, a sprite that waits for the display was still drawn
+        Limit::Instructions(instructions) => {
+            let per_frame = (chip.instructions_per_second() / TIMER_FREQUENCY).max(1) as u64;
+            let mut executed = 0;
+            let mut frame = 0;
+            while executed < instructions {
+                chip.kb = keys.keys(frame);
+                let mut waiting_for_key = false;
+                for _ in 0..per_frame.min(instructions - executed) {
+                    match chip.tick().map_err(|e| (frame + 1, e))? {
+                        StepOutcome::Executed => executed += 1,
+                        StepOutcome::WaitingForDisplay => {
+                            executed += 1;
+                            break;
+                        }
+                        StepOutcome::WaitingForKey => {
+                            waiting_for_key = true;
+                            break;
+                        }
+                        StepOutcome::Exited => return Ok(frame + 1),
+                    }
+                }
+                chip.tick_timers();
+                frame += 1;
+                // with no presses left in the script it would wait for ever
+                if waiting_for_key && keys.last_frame().is_none_or(|last| last < frame) {
+                    break;
+                }
+            }
+            Ok(frame)
+        }
+    }
+}
+
+/// The display, one character per pixel and one line per row.
+pub fn display_to_ascii(chip: &Chip8) -> String {
+    let width = chip.display_width();
+    let mut out = String::with_capacity((width + 1) * chip.display_height());
+    for row in chip.display.chunks(width).take(chip.display_height()) {
+        out.extend(row.iter().map(|planes| ASCII_PALETTE[*planes as usize & 3]));
+        out.push('\n');
+    }
+    out
+}
+
+/// The registers, stack and timers as a JSON object.
+pub fn registers_json(chip: &Chip8, frames: u64, error: Option<&Chip8Error>) -> String {
+    let list = |values: &mut dyn Iterator<Item = u16>| {
+        values.map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+    };
+    let mut out = String::from("{\n");
+    let _ = writeln!(
+        out,
+        "  \"v\": [{}],",
+        list(&mut chip.regs().iter().map(|v| *v as u16))
+    );
+    let _ = writeln!(out, "  \"i\": {},", chip.ireg());
+    let _ = writeln!(out, "  \"pc\": {},", chip.pc());
+    let _ = writeln!(out, "  \"sp\": {},", chip.sp());
+    let _ = writeln!(
+        out,
+        "  \"stack\": [{}],",
+        list(&mut chip.stack().iter().copied())
+    );
+    let _ = writeln!(out, "  \"dt\": {},", chip.dreg());
+    let _ = writeln!(out, "  \"st\": {},", chip.sreg);
+    let _ = writeln!(out, "  \"frames\": {frames},");
+    let _ = writeln!(out, "  \"exited\": {},", chip.has_exited());
+    match error {
+        // error messages never contain quotes or backslashes
+        Some(e) => {
+            let _ = writeln!(out, "  \"error\": \"{e}\"");
+        }
+        None => out.push_str("  \"error\": null\n"),
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The display as a PNG, each pixel drawn as a `scale` by `scale` square.
+pub fn display_to_png(chip: &Chip8, scale: usize) -> Vec<u8> {
+    let width = chip.display_width();
+    let height = chip.display_height();
+    // every row starts with the filter type byte, 0 for none
+    let mut pixels = Vec::with_capacity((width * scale * 3 + 1) * height * scale);
+    for row in chip.display.chunks(width).take(height) {
+        let mut line = vec![0];
+        for planes in row {
+            for _ in 0..scale {
+                line.extend_from_slice(&PNG_PALETTE[*planes as usize & 3]);
+            }
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&((width * scale) as u32).to_be_bytes());
+    header.extend_from_slice(&((height * scale) as u32).to_be_bytes());
+    // 8 bit rgb, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// a zlib stream of uncompressed blocks. the images are tiny, compressing them isn't worth the code
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
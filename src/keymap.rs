@@ -0,0 +1,286 @@
+// which host keys press which chip-8 key. any number of host keys can press the same chip-8 key,
+// a host key only ever presses one. in the config a keys table lists host keys by chip-8 key,
+// replacing what the layout had for the chip-8 keys it names:
+//
+//   [roms.<sha-1>.keys]
+//   4 = ["Left", "Q"]
+//   6 = ["Right", "E"]
+
+use std::collections::BTreeMap;
+
+use macroquad::prelude::{is_key_down, KeyCode};
+
+// the 1234/QWER/ASDF/ZXCV block, indexed by chip-8 key
+const DEFAULT_LAYOUT: [KeyCode; 16] = [
+    KeyCode::X,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Q,
+    KeyCode::W,
+    KeyCode::E,
+    KeyCode::A,
+    KeyCode::S,
+    KeyCode::D,
+    KeyCode::Z,
+    KeyCode::C,
+    KeyCode::Key4,
+    KeyCode::R,
+    KeyCode::F,
+    KeyCode::V,
+];
+
+// the hotkeys of Program::process_sys_input and the debugger, which can't press a chip-8 key too
+pub const RESERVED_KEYS: [KeyCode; 15] = [
+    KeyCode::Escape,
+    KeyCode::Backspace,
+    KeyCode::Enter,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+];
+
+// every key that has a name, for looking them up by it
+const NAMED_KEYS: [KeyCode; 120] = [
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Semicolon,
+    KeyCode::Equal,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::LeftBracket,
+    KeyCode::Backslash,
+    KeyCode::RightBracket,
+    KeyCode::GraveAccent,
+    KeyCode::World1,
+    KeyCode::World2,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::CapsLock,
+    KeyCode::ScrollLock,
+    KeyCode::NumLock,
+    KeyCode::PrintScreen,
+    KeyCode::Pause,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::F13,
+    KeyCode::F14,
+    KeyCode::F15,
+    KeyCode::F16,
+    KeyCode::F17,
+    KeyCode::F18,
+    KeyCode::F19,
+    KeyCode::F20,
+    KeyCode::F21,
+    KeyCode::F22,
+    KeyCode::F23,
+    KeyCode::F24,
+    KeyCode::F25,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpDecimal,
+    KeyCode::KpDivide,
+    KeyCode::KpMultiply,
+    KeyCode::KpSubtract,
+    KeyCode::KpAdd,
+    KeyCode::KpEnter,
+    KeyCode::KpEqual,
+    KeyCode::LeftShift,
+    KeyCode::LeftControl,
+    KeyCode::LeftAlt,
+    KeyCode::LeftSuper,
+    KeyCode::RightShift,
+    KeyCode::RightControl,
+    KeyCode::RightAlt,
+    KeyCode::RightSuper,
+    KeyCode::Menu,
+];
+
+#[derive(Clone, PartialEq)]
+pub struct KeyMap {
+    // indexed by chip-8 key
+    keys: [Vec<KeyCode>; 16],
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap {
+            keys: DEFAULT_LAYOUT.map(|key| vec![key]),
+        }
+    }
+}
+
+impl KeyMap {
+    pub fn host_keys(&self, chip_key: usize) -> &[KeyCode] {
+        &self.keys[chip_key]
+    }
+
+    // the chip-8 key a host key presses, if any
+    pub fn chip_key(&self, key: KeyCode) -> Option<usize> {
+        self.keys.iter().position(|keys| keys.contains(&key))
+    }
+
+    // moves key over from whichever chip-8 key it pressed before
+    pub fn bind(&mut self, chip_key: usize, key: KeyCode) -> Result<(), String> {
+        if RESERVED_KEYS.contains(&key) {
+            return Err(format!("{} is a hotkey", key_name(key)));
+        }
+        for keys in &mut self.keys {
+            keys.retain(|k| *k != key);
+        }
+        self.keys[chip_key].push(key);
+        Ok(())
+    }
+
+    pub fn clear(&mut self, chip_key: usize) {
+        self.keys[chip_key].clear();
+    }
+
+    pub fn fill(&self, kb: &mut [u8; 16]) {
+        for (pressed, keys) in kb.iter_mut().zip(&self.keys) {
+            *pressed = keys.iter().any(|key| is_key_down(*key)) as u8;
+        }
+    }
+
+    // a keys table from the config, see the top of the file. what doesn't make sense is reported
+    // and left out
+    pub fn apply(&mut self, table: &BTreeMap<String, Vec<String>>, warn: impl Fn(&str)) {
+        for (chip_key, names) in table {
+            let Some(chip_key) = u8::from_str_radix(chip_key, 16).ok().filter(|k| *k < 16) else {
+                warn(&format!("ignoring keys.{chip_key}: not a chip-8 key"));
+                continue;
+            };
+            self.clear(chip_key as usize);
+            for name in names {
+                let result = key_from_name(name)
+                    .ok_or_else(|| "there's no key called that".to_string())
+                    .and_then(|key| self.bind(chip_key as usize, key));
+                if let Err(e) = result {
+                    warn(&format!("ignoring keys.{chip_key:X} = {name}: {e}"));
+                }
+            }
+        }
+    }
+
+    // the whole layout as a keys table
+    pub fn to_table(&self) -> BTreeMap<String, Vec<String>> {
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(chip_key, keys)| {
+                let names = keys.iter().map(|key| key_name(*key)).collect();
+                (format!("{chip_key:X}"), names)
+            })
+            .collect()
+    }
+
+    // the arrow keys for the directions the rom database names, on top of the layout. an arrow key
+    // that already presses something is left alone
+    pub fn add_hints(&mut self, hints: &[(String, u8)]) {
+        for (name, chip_key) in hints {
+            let key = match name.as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                _ => continue,
+            };
+            if *chip_key < 16 && self.chip_key(key).is_none() {
+                let _ = self.bind(*chip_key as usize, key);
+            }
+        }
+    }
+}
+
+// the digits are called 1 rather than Key1, everything else what macroquad calls it
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{key:?}");
+    match name.strip_prefix("Key") {
+        Some(digit) => digit.to_string(),
+        None => name,
+    }
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    NAMED_KEYS
+        .iter()
+        .find(|key| key_name(**key).eq_ignore_ascii_case(name))
+        .copied()
+}
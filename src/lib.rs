@@ -0,0 +1,27 @@
+//! The chippi interpreter core: CHIP-8, Super-Chip and XO-Chip, without any windowing or audio backend.
+//!
+//! ```no_run
+//! use chippi::{Chip8, Computer, Quirks};
+//!
+//! let rom = std::fs::read("roms/chippi.ch8").unwrap();
+//! let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::default(), &rom).unwrap();
+//! chip.run_frame().unwrap();
+//! ```
+
+pub mod asm;
+pub mod audio;
+pub mod chip8;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
+pub mod headless;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod state;
+
+pub use chip8::{Chip8, Chip8Error, Computer, MemoryAccess, StepOutcome};
+pub use quirks::Quirks;
+pub use rng::{Rng, XorShiftRng, DEFAULT_SEED};
@@ -1,51 +1,78 @@
 extern crate core;
 
+mod cli;
+mod commands;
+mod config;
+mod keymap;
 mod program;
-mod chip8;
+#[cfg(all(feature = "tty", not(target_arch = "wasm32")))]
+mod tty;
 
 use std::collections::VecDeque;
-use program::{DEFAULT_PIXEL_SIZE, JSEvents};
-use chip8::{DISPLAY_WIDTH, DISPLAY_HEIGHT};
+use program::JSEvents;
+use chippi::chip8::{DISPLAY_WIDTH, DISPLAY_HEIGHT};
 
-fn get_mq_conf() -> macroquad::prelude::Conf {
+fn get_mq_conf(options: &cli::RunOptions) -> macroquad::prelude::Conf {
     //window config
     macroquad::prelude::Conf {
         window_title: String::from("chippi"),
-        window_width: DEFAULT_PIXEL_SIZE * DISPLAY_WIDTH as i32,
-        window_height: DEFAULT_PIXEL_SIZE * DISPLAY_HEIGHT as i32,
-        fullscreen: false,
+        window_width: options.scale * DISPLAY_WIDTH as i32,
+        window_height: options.scale * DISPLAY_HEIGHT as i32,
+        fullscreen: options.fullscreen,
         ..Default::default()
     }
 }
 
 static mut EVENTS: Option<VecDeque<JSEvents>> = None;
 
-#[macroquad::main(get_mq_conf)]
-async fn main() {
-    let (file, speed, sound, mode) = program::process_env_variables().await;
-    let mut program = program::Program::init(file, speed, sound, mode).await;
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // everything but the window finishes before one is ever opened
+    let options = match cli::parse(&args) {
+        Ok(cli::Command::Run(options)) => options,
+        Ok(command) => std::process::exit(commands::run(command)),
+        Err(e) => {
+            eprintln!("chippi: {e}\nRun chippi --help to see the options");
+            std::process::exit(2);
+        }
+    };
+    // checked up front so a typo doesn't flash a window open. the web fetches its roms later
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(rom) = &options.rom {
+        if let Err(e) = std::fs::metadata(rom) {
+            eprintln!("chippi: Couldn't read {rom}: {e}");
+            std::process::exit(1);
+        }
+    }
+    macroquad::Window::from_config(get_mq_conf(&options), run(options));
+}
+
+async fn run(options: cli::RunOptions) {
+    let options = program::load_options(options).await;
+    let config = config::Config::load();
+    let mut program = program::Program::init(options, config).await;
     unsafe {
         EVENTS = Some(VecDeque::new());
-        while program.run(&mut EVENTS).await {}
+        while program.run(&mut *std::ptr::addr_of_mut!(EVENTS)).await {}
     }
 }
 
-unsafe fn push_event(event: JSEvents) {
-    match &mut EVENTS {
-        Some(evs) => {
+fn push_event(event: JSEvents) {
+    // the js callbacks and the main loop run on the same thread
+    unsafe {
+        if let Some(evs) = &mut *std::ptr::addr_of_mut!(EVENTS) {
             evs.push_back(event);
         }
-        _ => {}
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ev_change_color(color_number: i32) {
+pub extern "C" fn ev_change_color(color_number: i32) {
     push_event(JSEvents::ChangeColor(color_number));
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ev_swap_rom(rom_number: i32) {
+pub extern "C" fn ev_swap_rom(rom_number: i32) {
 
     let rom_filename:String = match rom_number {
         0 => {
@@ -66,11 +93,16 @@ pub unsafe extern "C" fn ev_swap_rom(rom_number: i32) {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ev_change_speed(new_speed: i32) {
+pub extern "C" fn ev_change_speed(new_speed: i32) {
     push_event(JSEvents::ChangeSpeed(new_speed));
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ev_change_rainbow_mode(color_number: i32) {
+pub extern "C" fn ev_change_rainbow_mode(color_number: i32) {
     push_event(JSEvents::ChangeRainbowMode(color_number));
 }
+
+#[no_mangle]
+pub extern "C" fn ev_change_computer(computer_id: i32) {
+    push_event(JSEvents::ChangeComputer(computer_id));
+}
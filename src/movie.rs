@@ -0,0 +1,116 @@
+//! Input recordings ("movies") that replay a session exactly.
+//!
+//! Everything else a run depends on is deterministic, so a movie only needs the settings the
+//! machine was started with and the keyboard of every 60hz frame. Layout, little endian:
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 4     | magic `CHPM`                            |
+//! | 2     | version                                 |
+//! | 20    | SHA-1 of the ROM                        |
+//! | 1     | computer id                             |
+//! | 1     | quirk bits                              |
+//! | 4     | instructions per second                 |
+//! | 8     | RND seed                                |
+//! | 2 * n | one keyboard bitmask per frame, to EOF  |
+//!
+//! Frames are appended as they are played, so a recording cut short is still valid.
+
+use crate::chip8::{Chip8Error, Computer, INSTRUCTIONS_PER_SECOND_RANGE};
+use crate::quirks::Quirks;
+
+pub const MOVIE_MAGIC: &[u8; 4] = b"CHPM";
+pub const MOVIE_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 40;
+
+pub type RomHash = [u8; 20];
+
+pub fn rom_hash(rom: &[u8]) -> RomHash {
+    sha1_smol::Sha1::from(rom).digest().bytes()
+}
+
+// the lowercase hex form sha1sum prints, which the config file is keyed by
+pub fn rom_hash_hex(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+    pub rom_hash: RomHash,
+    pub computer: Computer,
+    pub quirks: Quirks,
+    pub ips: u32,
+    pub seed: u64,
+}
+
+impl MovieHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE);
+        out.extend_from_slice(MOVIE_MAGIC);
+        out.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash);
+        out.push(self.computer.id());
+        out.push(self.quirks.to_bits());
+        out.extend_from_slice(&self.ips.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out
+    }
+}
+
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn parse(data: &[u8]) -> Result<Movie, Chip8Error> {
+        if data.len() < HEADER_SIZE || &data[0..4] != MOVIE_MAGIC {
+            return Err(Chip8Error::InvalidMovie("not a chippi movie"));
+        }
+        if u16::from_le_bytes([data[4], data[5]]) != MOVIE_VERSION {
+            return Err(Chip8Error::InvalidMovie("movie version not supported"));
+        }
+        let header = MovieHeader {
+            rom_hash: data[6..26].try_into().unwrap(),
+            computer: Computer::from_id(data[26])
+                .ok_or(Chip8Error::InvalidMovie("unknown computer"))?,
+            quirks: Quirks::from_bits(data[27]),
+            ips: u32::from_le_bytes(data[28..32].try_into().unwrap()),
+            seed: u64::from_le_bytes(data[32..40].try_into().unwrap()),
+        };
+        //0 would replay a machine that never moves, a huge speed one that never finishes a frame
+        if !INSTRUCTIONS_PER_SECOND_RANGE.contains(&header.ips) {
+            return Err(Chip8Error::InvalidMovie("instructions per second out of range"));
+        }
+        //a trailing odd byte is a frame that was cut off while being written, drop it
+        let frames = data[HEADER_SIZE..]
+            .chunks_exact(2)
+            .map(|frame| u16::from_le_bytes([frame[0], frame[1]]))
+            .collect();
+        Ok(Movie { header, frames })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.header.to_bytes();
+        for frame in &self.frames {
+            out.extend_from_slice(&frame.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Packs the keyboard into one bit per key, bit n being key n.
+pub fn keys_to_bits(kb: &[u8; 16]) -> u16 {
+    kb.iter()
+        .enumerate()
+        .filter(|(_, down)| **down != 0)
+        .fold(0, |bits, (key, _)| bits | 1 << key)
+}
+
+pub fn bits_to_keys(bits: u16) -> [u8; 16] {
+    let mut kb = [0; 16];
+    for (key, down) in kb.iter_mut().enumerate() {
+        *down = (bits >> key & 1) as u8;
+    }
+    kb
+}
@@ -0,0 +1,1137 @@
+//! Compiles Octo source (`.8o`) into a ROM, the language most CHIP-8 homebrew since Octojam is written in.
+//!
+//! Supported: `:` labels, `:const`, `:alias`, `:unpack`, `:next`, `:org`, `:call`, `:byte`, `:macro`,
+//! `:calc`, `:assert`, every statement (`:=`, `+=`, `i := hex v0`, `sprite`...), `if ... then`,
+//! `if ... begin ... else ... end`, and `loop ... while ... again`. Super-Chip statements need a
+//! Super-Chip or XO-Chip computer, XO-Chip ones (`i := long`, `plane`, `audio`, `save v1 - v3`...)
+//! an XO-Chip one, just like the instructions they compile to.
+//!
+//! Execution starts at `: main`. Unless main is the very first thing in the program, a `jump main`
+//! is put at 0x200 (0x600 on the ETI 660).
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use crate::asm::AsmError;
+use crate::chip8::Computer;
+
+// macros expanding other macros this many times are assumed to never stop
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+// the register the comparisons other than == and != work in
+const COMPARE_TEMP: u8 = 0xF;
+
+/// Compiles `source` for `computer`.
+pub fn compile(source: &str, computer: Computer) -> std::result::Result<Vec<u8>, AsmError> {
+    Compiler::new(source, "<source>", computer).compile()
+}
+
+/// Compiles the `.8o` file at `path` for `computer`.
+pub fn compile_file(path: &Path, computer: Computer) -> std::result::Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError {
+        file: name.clone(),
+        line: 0,
+        message: format!("Couldn't read {name}: {e}"),
+    })?;
+    Compiler::new(&source, &name, computer).compile()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Fixup {
+    // the low 12 bits of an instruction
+    Nnn,
+    // both bytes, xo-chip's long load
+    Long,
+    // a byte holding part of an address: prefix | (addr >> shift) & mask
+    Byte { prefix: u8, shift: u32, mask: u16 },
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    file: String,
+    line: usize,
+    computer: Computer,
+    // where the rom is loaded
+    origin: usize,
+    // everything from origin up
+    rom: Vec<u8>,
+    here: usize,
+    jump_to_main: bool,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    // address, label and line of every reference to a label that wasn't defined yet
+    fixups: Vec<(usize, String, usize, Fixup)>,
+    // the jump of every open `if ... begin` or `else`
+    branches: Vec<usize>,
+    // start of every open loop and the jumps out of it its whiles made
+    loops: Vec<(usize, Vec<usize>)>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Compiler {
+    fn new(source: &str, file: &str, computer: Computer) -> Compiler {
+        Compiler {
+            tokens: tokenize(source),
+            file: file.to_string(),
+            line: 0,
+            computer,
+            origin: computer.program_start(),
+            rom: Vec::new(),
+            // room for the jump to main
+            here: computer.program_start() + 2,
+            jump_to_main: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn compile(mut self) -> std::result::Result<Vec<u8>, AsmError> {
+        match self.run() {
+            Ok(()) => Ok(self.rom),
+            Err(message) => Err(AsmError {
+                file: self.file,
+                line: self.line,
+                message,
+            }),
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.branches.is_empty() {
+            return Err("if ... begin without an end".to_string());
+        }
+        if !self.loops.is_empty() {
+            return Err("loop without an again".to_string());
+        }
+
+        for (addr, name, line, fixup) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let target = *self
+                .labels
+                .get(&name)
+                .ok_or_else(|| format!("Unknown label {name}"))?;
+            self.patch(addr, target, &fixup)?;
+        }
+        if self.jump_to_main {
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or("The program has no : main to start at")?;
+            self.patch_jump(self.origin, main)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String> {
+        let token = self.tokens.pop_front().ok_or("Unexpected end of file")?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek() == Some(text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token != text {
+            return Err(format!("Expected {text}, found {token}"));
+        }
+        Ok(())
+    }
+
+    fn require(&self, computer: Computer, what: &str) -> Result<()> {
+        let ok = match computer {
+            Computer::SuperChip => {
+                matches!(self.computer, Computer::SuperChip | Computer::XoChip)
+            }
+            Computer::XoChip => self.computer == Computer::XoChip,
+            _ => true,
+        };
+        if !ok {
+            let name = Computer::NAMES[computer.id() as usize];
+            return Err(format!("{what} needs --computer {name}"));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<()> {
+        let index = self.here - self.origin;
+        if self.here > 0xFFFF {
+            return Err("The program doesn't fit in memory".to_string());
+        }
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn inst(&mut self, opcode: u16) -> Result<()> {
+        let [hi, lo] = opcode.to_be_bytes();
+        self.emit(hi)?;
+        self.emit(lo)
+    }
+
+    fn patch(&mut self, addr: usize, target: usize, fixup: &Fixup) -> Result<()> {
+        let index = addr - self.origin;
+        match fixup {
+            Fixup::Nnn => {
+                if target > 0xFFF {
+                    return Err(format!(
+                        "Address 0x{target:X} is out of reach, use i := long"
+                    ));
+                }
+                self.rom[index] = self.rom[index] & 0xF0 | (target >> 8) as u8;
+                self.rom[index + 1] = target as u8;
+            }
+            Fixup::Long => {
+                self.rom[index] = (target >> 8) as u8;
+                self.rom[index + 1] = target as u8;
+            }
+            Fixup::Byte {
+                prefix,
+                shift,
+                mask,
+            } => self.rom[index] = prefix | ((target >> shift) as u16 & mask) as u8,
+        }
+        Ok(())
+    }
+
+    fn patch_jump(&mut self, addr: usize, target: usize) -> Result<()> {
+        let index = addr - self.origin;
+        if self.rom.len() < index + 2 {
+            self.rom.resize(index + 2, 0);
+        }
+        self.rom[index] = 0x10;
+        self.patch(addr, target, &Fixup::Nnn)
+    }
+
+    // an address that may be a label defined further down
+    fn address(&mut self, fixup: Fixup, at: usize) -> Result<usize> {
+        let token = self.next()?;
+        if let Some(addr) = self.labels.get(&token) {
+            return Ok(*addr);
+        }
+        if let Some(value) = self.number(&token) {
+            return Ok(value as usize);
+        }
+        if !is_name(&token) {
+            return Err(format!("Expected an address, found {token}"));
+        }
+        self.fixups.push((at, token, self.line, fixup));
+        Ok(0)
+    }
+
+    // an instruction with a 12 bit address at the bottom
+    fn inst_nnn(&mut self, opcode: u16) -> Result<()> {
+        let at = self.here;
+        let addr = self.address(Fixup::Nnn, at)?;
+        if addr > 0xFFF {
+            return Err(format!("Address 0x{addr:X} is out of reach, use i := long"));
+        }
+        self.inst(opcode | addr as u16)
+    }
+
+    fn number(&self, token: &str) -> Option<i64> {
+        if let Some(value) = self.constants.get(token) {
+            return Some(value.floor() as i64);
+        }
+        parse_number(token)
+    }
+
+    // a value that fits in max, negative numbers wrap around
+    fn value(&mut self, max: i64) -> Result<u16> {
+        let token = self.next()?;
+        let value = self
+            .number(&token)
+            .or_else(|| self.labels.get(&token).map(|addr| *addr as i64))
+            .ok_or_else(|| format!("Expected a number, found {token}"))?;
+        if value > max || value < -(max + 1) {
+            return Err(format!("{token} doesn't fit, the most is {max}"));
+        }
+        Ok((value & max) as u16)
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.as_register(&token)
+            .ok_or_else(|| format!("Expected a register, found {token}"))
+    }
+
+    fn as_register(&self, token: &str) -> Option<u8> {
+        if let Some(x) = self.aliases.get(token) {
+            return Some(*x);
+        }
+        let x = token.strip_prefix(['v', 'V'])?;
+        if x.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(x, 16).ok()
+    }
+
+    fn define_label(&mut self, name: &str, addr: usize) -> Result<()> {
+        if !is_name(name) || self.as_register(name).is_some() {
+            return Err(format!("{name} can't be used as a name"));
+        }
+        if self.labels.insert(name.to_string(), addr).is_some() {
+            return Err(format!("{name} is already defined"));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        if let Some(x) = self.as_register(&token) {
+            return self.register_statement(x);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                // main right at the start doesn't need to be jumped to
+                if name == "main" && self.here == self.origin + 2 && self.rom.is_empty() {
+                    self.here = self.origin;
+                    self.jump_to_main = false;
+                }
+                self.define_label(&name, self.here)?;
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define_label(&name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let token = self.next()?;
+                let value = self
+                    .number(&token)
+                    .ok_or_else(|| format!("Expected a number, found {token}"))?;
+                self.constants.insert(name, value as f64);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+            }
+            ":unpack" => {
+                let (prefix, mask) = if self.peek_is("long") {
+                    self.require(Computer::XoChip, ":unpack long")?;
+                    self.next()?;
+                    (0, 0xFF)
+                } else {
+                    ((self.value(0xF)? << 4) as u8, 0xF)
+                };
+                // v0 := the high part of the address, v1 := the low byte
+                let at = self.here;
+                let before = self.fixups.len();
+                let high = Fixup::Byte {
+                    prefix,
+                    shift: 8,
+                    mask,
+                };
+                let addr = self.address(high, at + 1)?;
+                if let Some((_, name, line, _)) = self.fixups.get(before) {
+                    let low = Fixup::Byte {
+                        prefix: 0,
+                        shift: 0,
+                        mask: 0xFF,
+                    };
+                    self.fixups.push((at + 3, name.clone(), *line, low));
+                }
+                self.inst(0x6000 | prefix as u16 | (addr >> 8) as u16 & mask)?;
+                self.inst(0x6100 | (addr & 0xFF) as u16)?;
+            }
+            ":org" => {
+                let addr = self.value(0xFFFF)? as usize;
+                if addr < self.origin {
+                    return Err(format!(":org 0x{addr:X} is below 0x{:X}", self.origin));
+                }
+                self.here = addr;
+            }
+            ":call" => self.inst_nnn(0x2000)?,
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc_block()?.floor() as i64 as u16 & 0xFF
+                } else {
+                    self.value(0xFF)?
+                };
+                self.emit(value as u8)?;
+            }
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => {
+                        self.next()?.trim_matches('"').to_string()
+                    }
+                    _ => "assertion failed".to_string(),
+                };
+                if self.calc_block()? == 0.0 {
+                    return Err(message);
+                }
+            }
+            ":macro" => self.define_macro()?,
+            // debugger hints, nothing to compile
+            ":breakpoint" | ":proto" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.inst(0x00EE)?,
+            "clear" => self.inst(0x00E0)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.inst(0xF033 | (x as u16) << 8)?;
+            }
+            "save" | "load" => {
+                let save = token == "save";
+                let x = self.register()?;
+                if self.peek_is("-") {
+                    self.require(Computer::XoChip, "Saving and loading register ranges")?;
+                    self.next()?;
+                    let y = self.register()?;
+                    let opcode = if save { 0x5002 } else { 0x5003 };
+                    self.inst(opcode | (x as u16) << 8 | (y as u16) << 4)?;
+                } else {
+                    let opcode = if save { 0xF055 } else { 0xF065 };
+                    self.inst(opcode | (x as u16) << 8)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value(0xF)?;
+                self.inst(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n)?;
+            }
+            "jump" => self.inst_nnn(0x1000)?,
+            "jump0" => self.inst_nnn(0xB000)?,
+            "native" => self.inst_nnn(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let opcode = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => {
+                        self.require(Computer::XoChip, "pitch")?;
+                        0xF03A
+                    }
+                };
+                self.inst(opcode | x << 8)?;
+            }
+            "i" => self.i_statement()?,
+            "hires" | "lores" | "scroll-left" | "scroll-right" | "exit" => {
+                self.require(Computer::SuperChip, &token)?;
+                let opcode = match token.as_str() {
+                    "hires" => 0x00FF,
+                    "lores" => 0x00FE,
+                    "scroll-left" => 0x00FC,
+                    "scroll-right" => 0x00FB,
+                    _ => 0x00FD,
+                };
+                self.inst(opcode)?;
+            }
+            "scroll-down" => {
+                self.require(Computer::SuperChip, &token)?;
+                let n = self.value(0xF)?;
+                self.inst(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                self.require(Computer::XoChip, &token)?;
+                let n = self.value(0xF)?;
+                self.inst(0x00D0 | n)?;
+            }
+            "saveflags" | "loadflags" => {
+                self.require(Computer::SuperChip, &token)?;
+                let x = self.register()? as u16;
+                let opcode = if token == "saveflags" { 0xF075 } else { 0xF085 };
+                self.inst(opcode | x << 8)?;
+            }
+            "plane" => {
+                self.require(Computer::XoChip, &token)?;
+                let n = self.value(0xF)?;
+                self.inst(0xF001 | n << 8)?;
+            }
+            "audio" => {
+                self.require(Computer::XoChip, &token)?;
+                self.inst(0xF002)?;
+            }
+            "if" => self.if_statement()?,
+            "else" => {
+                let jump = self.branches.pop().ok_or("else without if ... begin")?;
+                let here = self.here;
+                self.inst(0x1000)?;
+                self.patch_jump(jump, self.here)?;
+                self.branches.push(here);
+            }
+            "end" => {
+                let jump = self.branches.pop().ok_or("end without if ... begin")?;
+                self.patch_jump(jump, self.here)?;
+            }
+            "loop" => self.loops.push((self.here, Vec::new())),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err("while outside of a loop".to_string());
+                }
+                self.condition(true)?;
+                let here = self.here;
+                self.inst(0x1000)?;
+                self.loops.last_mut().unwrap().1.push(here);
+            }
+            "again" => {
+                let (start, exits) = self.loops.pop().ok_or("again without loop")?;
+                let here = self.here;
+                self.inst(0x1000)?;
+                self.patch_jump(here, start)?;
+                for exit in exits {
+                    self.patch_jump(exit, self.here)?;
+                }
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // a number on its own is a byte of data, a name on its own calls it
+            _ => match self.number(&token) {
+                Some(value) if (-128..=255).contains(&value) => self.emit(value as u8)?,
+                Some(_) => return Err(format!("{token} doesn't fit in a byte")),
+                None if is_name(&token) => {
+                    self.tokens.push_front(Token {
+                        text: token,
+                        line: self.line,
+                    });
+                    self.inst_nnn(0x2000)?;
+                }
+                None => return Err(format!("Unexpected {token}")),
+            },
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<()> {
+        let op = self.next()?;
+        let x = x as u16;
+        let rhs = self.peek().map(|t| t.to_string()).unwrap_or_default();
+        let y = self.as_register(&rhs).map(|y| y as u16);
+        let arith = |n: u16| -> Result<u16> {
+            match y {
+                Some(y) => Ok(0x8000 | x << 8 | y << 4 | n),
+                None => Err(format!("{op} needs a register on the right, found {rhs}")),
+            }
+        };
+        let opcode = match op.as_str() {
+            ":=" => match (rhs.as_str(), y) {
+                (_, Some(y)) => 0x8000 | x << 8 | y << 4,
+                ("random", _) => {
+                    self.next()?;
+                    let n = self.value(0xFF)?;
+                    return self.inst(0xC000 | x << 8 | n);
+                }
+                ("key", _) => 0xF00A | x << 8,
+                ("delay", _) => 0xF007 | x << 8,
+                _ => {
+                    let n = self.value(0xFF)?;
+                    return self.inst(0x6000 | x << 8 | n);
+                }
+            },
+            "+=" if y.is_none() => {
+                let n = self.value(0xFF)?;
+                return self.inst(0x7000 | x << 8 | n);
+            }
+            "-=" if y.is_none() => {
+                let n = self.value(0xFF)?;
+                return self.inst(0x7000 | x << 8 | (n as u8).wrapping_neg() as u16);
+            }
+            "+=" => arith(0x4)?,
+            "-=" => arith(0x5)?,
+            "=-" => arith(0x7)?,
+            "|=" => arith(0x1)?,
+            "&=" => arith(0x2)?,
+            "^=" => arith(0x3)?,
+            ">>=" => arith(0x6)?,
+            "<<=" => arith(0xE)?,
+            _ => return Err(format!("Unknown operator {op}")),
+        };
+        self.next()?;
+        self.inst(opcode)
+    }
+
+    fn i_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("long") => {
+                    self.require(Computer::XoChip, "i := long")?;
+                    self.next()?;
+                    self.inst(0xF000)?;
+                    let at = self.here;
+                    let addr = self.address(Fixup::Long, at)?;
+                    if addr > 0xFFFF {
+                        return Err(format!("Address 0x{addr:X} is out of reach"));
+                    }
+                    self.inst(addr as u16)
+                }
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.inst(0xF029 | x << 8)
+                }
+                Some("bighex") => {
+                    self.require(Computer::SuperChip, "i := bighex")?;
+                    self.next()?;
+                    let x = self.register()? as u16;
+                    self.inst(0xF030 | x << 8)
+                }
+                _ => self.inst_nnn(0xA000),
+            },
+            "+=" => {
+                let x = self.register()? as u16;
+                self.inst(0xF01E | x << 8)
+            }
+            _ => Err(format!("Unknown operator {op} for i")),
+        }
+    }
+
+    // if <condition> then <statement>, or if <condition> begin ... [else ...] end
+    fn if_statement(&mut self) -> Result<()> {
+        // the condition is only known to be a begin block once it has been read
+        let block = self
+            .tokens
+            .iter()
+            .take(5)
+            .find(|t| t.text == "then" || t.text == "begin")
+            .map(|t| t.text == "begin")
+            .ok_or("if needs a then or a begin")?;
+        self.condition(block)?;
+        if block {
+            self.expect("begin")?;
+            let here = self.here;
+            self.inst(0x1000)?;
+            self.branches.push(here);
+        } else {
+            self.expect("then")?;
+        }
+        Ok(())
+    }
+
+    // compiles to instructions that let the next one run only when the condition holds,
+    // or when it doesn't if negated
+    fn condition(&mut self, negated: bool) -> Result<()> {
+        let x = self.register()? as u16;
+        let mut op = self.next()?;
+        if negated {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                "<=" => ">",
+                _ => return Err(format!("Unknown comparison {op}")),
+            }
+            .to_string();
+        }
+        match op.as_str() {
+            "key" => return self.inst(0xE0A1 | x << 8),
+            "-key" => return self.inst(0xE09E | x << 8),
+            _ => {}
+        }
+        let rhs = self.peek().map(|t| t.to_string()).unwrap_or_default();
+        let y = self.as_register(&rhs).map(|y| y as u16);
+        match (op.as_str(), y) {
+            ("==", Some(y)) => self.inst(0x9000 | x << 8 | y << 4)?,
+            ("!=", Some(y)) => self.inst(0x5000 | x << 8 | y << 4)?,
+            ("==" | "!=", None) => {
+                let n = self.value(0xFF)?;
+                let opcode = if op == "==" { 0x4000 } else { 0x3000 };
+                return self.inst(opcode | x << 8 | n);
+            }
+            ("<" | ">" | "<=" | ">=", _) => {
+                // vF := rhs, then subtract one from the other so the borrow flag lands in vF
+                let t = COMPARE_TEMP as u16;
+                match y {
+                    Some(y) => self.inst(0x8000 | t << 8 | y << 4)?,
+                    None => {
+                        let n = self.value(0xFF)?;
+                        self.inst(0x6000 | t << 8 | n)?;
+                    }
+                }
+                if y.is_some() {
+                    self.next()?;
+                }
+                // < and >= look at x - rhs, > and <= at rhs - x. both set vF to 1 unless it borrowed
+                let subtract = if matches!(op.as_str(), "<" | ">=") {
+                    0x7
+                } else {
+                    0x5
+                };
+                self.inst(0x8000 | t << 8 | x << 4 | subtract)?;
+                // < and > hold when it borrowed
+                let skip_if = if matches!(op.as_str(), "<" | ">") {
+                    1
+                } else {
+                    0
+                };
+                return self.inst(0x3000 | t << 8 | skip_if);
+            }
+            _ => return Err(format!("Unknown comparison {op}")),
+        }
+        self.next()?;
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.next()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+        let body = self.block()?;
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(format!("Macro {name} keeps expanding"));
+        }
+        let count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for i in 0..count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[i].clone(), value);
+        }
+        let line = self.line;
+        let body = &self.macros[name].body;
+        for token in body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text).clone();
+            // errors inside the macro are reported where it was used
+            self.tokens.push_front(Token { text, line });
+        }
+        Ok(())
+    }
+
+    // the tokens up to the } matching a { that has already been read
+    fn block(&mut self) -> Result<Vec<Token>> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.tokens.pop_front().ok_or("{ without a matching }")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn calc_block(&mut self) -> Result<f64> {
+        self.expect("{")?;
+        let tokens: Vec<String> = self.block()?.into_iter().map(|t| t.text).collect();
+        let mut pos = 0;
+        let value = self.calc(&tokens, &mut pos)?;
+        if pos < tokens.len() {
+            return Err(format!("Unexpected {} in calculation", tokens[pos]));
+        }
+        Ok(value)
+    }
+
+    // octo evaluates right to left, every binary operator binding the same
+    fn calc(&self, tokens: &[String], pos: &mut usize) -> Result<f64> {
+        let left = self.calc_term(tokens, pos)?;
+        let Some(op) = tokens.get(*pos).filter(|op| op.as_str() != ")") else {
+            return Ok(left);
+        };
+        *pos += 1;
+        let right = self.calc(tokens, pos)?;
+        let int = |v: f64| v as i64;
+        let shift = |v: f64| {
+            u32::try_from(int(v))
+                .ok()
+                .filter(|n| *n < i64::BITS)
+                .ok_or_else(|| format!("Shift of {v} out of range in calculation"))
+        };
+        let value = match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (int(left) & int(right)) as f64,
+            "|" => (int(left) | int(right)) as f64,
+            "^" => (int(left) ^ int(right)) as f64,
+            "<<" => (int(left) << shift(right)?) as f64,
+            ">>" => (int(left) >> shift(right)?) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("Unknown operator {op} in calculation")),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&self, tokens: &[String], pos: &mut usize) -> Result<f64> {
+        let token = tokens.get(*pos).ok_or("Calculation ends too early")?;
+        *pos += 1;
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| -> Result<f64> {
+            Ok(f(self.calc_term(tokens, pos)?))
+        };
+        match token.as_str() {
+            "(" => {
+                let value = self.calc(tokens, pos)?;
+                if tokens.get(*pos).map(String::as_str) != Some(")") {
+                    return Err("( without a matching )".to_string());
+                }
+                *pos += 1;
+                Ok(value)
+            }
+            "-" => unary(|v| -v, pos),
+            "~" => unary(|v| !(v as i64) as f64, pos),
+            "!" => unary(|v| (v == 0.0) as i64 as f64, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "tan" => unary(f64::tan, pos),
+            "exp" => unary(f64::exp, pos),
+            "log" => unary(f64::ln, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "sign" => unary(f64::signum, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "floor" => unary(f64::floor, pos),
+            // a byte the program has compiled so far
+            "@" => {
+                let addr = self.calc_term(tokens, pos)? as usize;
+                let byte = addr
+                    .checked_sub(self.origin)
+                    .and_then(|i| self.rom.get(i))
+                    .copied()
+                    .unwrap_or(0);
+                Ok(byte as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => {
+                if let Some(value) = self.constants.get(token) {
+                    return Ok(*value);
+                }
+                if let Some(addr) = self.labels.get(token) {
+                    return Ok(*addr as f64);
+                }
+                if let Some(x) = self.as_register(token) {
+                    return Ok(x as f64);
+                }
+                parse_number(token)
+                    .map(|v| v as f64)
+                    .ok_or_else(|| format!("Unknown name {token} in calculation"))
+            }
+        }
+    }
+}
+
+// tokens are separated by whitespace, # starts a comment
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            rest = &rest[start..];
+            if rest.starts_with('#') {
+                break;
+            }
+            // strings keep their spaces
+            let end = if let Some(string) = rest.strip_prefix('"') {
+                string.find('"').map_or(rest.len(), |end| end + 2)
+            } else {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+            tokens.push_back(Token {
+                text: rest[..end].to_string(),
+                line: i + 1,
+            });
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    // compiles `octo` and checks it came out as `expected`, written for the assembler
+    fn assert_compiles_to(octo: &str, expected: &str) {
+        let rom = compile(octo, Computer::Normal).unwrap_or_else(|e| panic!("{e}"));
+        let expected = asm::assemble(expected, Computer::Normal).unwrap();
+        assert_eq!(rom, expected, "{octo}");
+    }
+
+    // the line and message `octo` fails with
+    fn failure(octo: &str, computer: Computer) -> (usize, String) {
+        let e = compile(octo, computer).expect_err("should fail");
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_compiles_to(": main clear", "CLS");
+        assert_compiles_to(
+            ": data 1 2 : main clear",
+            "JP 0x204
+             db 1, 2
+             CLS",
+        );
+    }
+
+    #[test]
+    fn macros_substitute_their_arguments() {
+        assert_compiles_to(
+            ":macro bump reg amount { reg += amount reg += amount }
+             : main bump v3 2 bump v4 1",
+            "ADD V3, 2
+             ADD V3, 2
+             ADD V4, 1
+             ADD V4, 1",
+        );
+    }
+
+    #[test]
+    fn constants_and_calculations() {
+        // octo evaluates right to left: BASE * (2 + 1)
+        assert_compiles_to(
+            ":const BASE 8
+             :calc TRIPLE { BASE * 2 + 1 }
+             :calc PAIR { ( BASE * 2 ) + 1 }
+             : main v0 := TRIPLE v1 := PAIR :byte { BASE << 4 }",
+            "LD V0, 24
+             LD V1, 17
+             db 0x80",
+        );
+    }
+
+    #[test]
+    fn shifts_out_of_range() {
+        assert_eq!(
+            failure(":calc x { 1 << 64 }\n: main clear", Computer::Normal),
+            (1, "Shift of 64 out of range in calculation".to_string())
+        );
+        assert_eq!(
+            failure(": main clear\n:calc x { 1 >> -1 }", Computer::Normal),
+            (2, "Shift of -1 out of range in calculation".to_string())
+        );
+        assert_compiles_to(":calc x { 1 << 63 >> 63 } : main v0 := x", "LD V0, 1");
+    }
+
+    #[test]
+    fn if_then_skips_one_statement() {
+        assert_compiles_to(
+            ": main if v0 == 5 then v1 := 1 if v0 != v2 then v1 := 2",
+            "SNE V0, 5
+             LD V1, 1
+             SE V0, V2
+             LD V1, 2",
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        assert_compiles_to(
+            ": main if v0 != v1 begin v2 := 1 else v2 := 2 end clear",
+            "SNE V0, V1
+             JP else
+             LD V2, 1
+             JP end
+             else: LD V2, 2
+             end: CLS",
+        );
+        assert_compiles_to(
+            ": main if v0 key begin v2 := 1 end",
+            "SKP V0
+             JP end
+             LD V2, 1
+             end:",
+        );
+    }
+
+    #[test]
+    fn loops_with_whiles() {
+        assert_compiles_to(
+            ": main loop v0 += 1 while v0 != 10 again clear",
+            "start: ADD V0, 1
+             SNE V0, 10
+             JP end
+             JP start
+             end: CLS",
+        );
+    }
+
+    // v2 ends up 1 when `v0 <op> v1` holds, with v1 a register and then a number
+    fn comparison_holds(a: u8, op: &str, b: u8) -> bool {
+        let results: Vec<u8> = ["v1".to_string(), b.to_string()]
+            .iter()
+            .map(|rhs| {
+                let source = format!(
+                    ": main v0 := {a} v1 := {b} v2 := 0 if v0 {op} {rhs} then v2 := 1 : halt jump halt"
+                );
+                let rom = compile(&source, Computer::Normal).unwrap();
+                let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::MODERN, &rom).unwrap();
+                for _ in 0..16 {
+                    chip.tick().unwrap();
+                }
+                chip.regs()[2]
+            })
+            .collect();
+        assert_eq!(
+            results[0], results[1],
+            "v0 {op} with a register and a number"
+        );
+        results[0] == 1
+    }
+
+    #[test]
+    fn comparisons() {
+        for (a, b) in [(3, 5), (5, 3), (4, 4), (0, 255), (255, 0)] {
+            assert_eq!(comparison_holds(a, "==", b), a == b);
+            assert_eq!(comparison_holds(a, "!=", b), a != b);
+            assert_eq!(comparison_holds(a, "<", b), a < b, "{a} < {b}");
+            assert_eq!(comparison_holds(a, ">", b), a > b, "{a} > {b}");
+            assert_eq!(comparison_holds(a, "<=", b), a <= b, "{a} <= {b}");
+            assert_eq!(comparison_holds(a, ">=", b), a >= b, "{a} >= {b}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let chip8 = Computer::Normal;
+        assert_eq!(
+            failure(": main\n  v0 := 256\n", chip8),
+            (2, "256 doesn't fit, the most is 255".to_string())
+        );
+        assert_eq!(
+            failure(": main\n\n  jump nowhere", chip8),
+            (3, "Unknown label nowhere".to_string())
+        );
+        assert_eq!(
+            failure(": main\n  v0 += 1\n  while v0 != 3", chip8),
+            (3, "while outside of a loop".to_string())
+        );
+        assert_eq!(
+            failure(": main\n  hires", chip8),
+            (2, "hires needs --computer schip".to_string())
+        );
+        assert_eq!(
+            failure(": main\n: main", chip8),
+            (2, "main is already defined".to_string())
+        );
+        assert_eq!(
+            failure(": start clear", chip8).1,
+            "The program has no : main to start at"
+        );
+        assert_eq!(
+            failure(": main if v0 == 1 begin clear", chip8).1,
+            "if ... begin without an end"
+        );
+        assert_eq!(
+            failure(": main loop clear", chip8).1,
+            "loop without an again"
+        );
+    }
+
+    #[test]
+    fn macro_errors_point_at_the_use() {
+        let source = ":macro set reg { reg := 300 }\n: main\n  set v0";
+        assert_eq!(
+            failure(source, Computer::Normal),
+            (3, "300 doesn't fit, the most is 255".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_assertions() {
+        assert_eq!(
+            failure(
+                ": main clear clear\n:assert \"main is too big\" { HERE <= 0x202 }",
+                Computer::Normal
+            ),
+            (2, "main is too big".to_string())
+        );
+    }
+}
@@ -1,107 +1,504 @@
-use std::collections::VecDeque;
-use std::env;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
-use crate::chip8::{Chip8, Computer, DISPLAY_WIDTH, DISPLAY_HEIGHT};
+use chippi::audio;
+use chippi::debugger::Debugger;
+use chippi::disasm;
+use chippi::chip8::{
+    Chip8, Chip8Error, Computer, AUDIO_PATTERN_SIZE, DEFAULT_INSTRUCTIONS_PER_SECOND,
+    INSTRUCTIONS_PER_SECOND_RANGE, TIMER_FREQUENCY,
+};
+use chippi::movie::{self, Movie, MovieHeader};
+use chippi::octo;
+use chippi::quirks::Quirks;
+use chippi::rewind::RewindBuffer;
+
+use crate::cli::RunOptions;
+use crate::config::{self, Config, Settings};
+use crate::keymap::{self, KeyMap};
 
-use macroquad::audio::{load_sound, play_sound, PlaySoundParams, Sound};
+use macroquad::file::load_file;
+use macroquad::miniquad::date;
+use macroquad::audio::{
+    load_sound, load_sound_from_bytes, play_sound, stop_sound, PlaySoundParams, Sound,
+};
 use macroquad::prelude::{
-    clear_background, draw_rectangle, is_key_down, is_key_pressed, next_frame, screen_width, Color,
-    KeyCode,
+    clear_background, draw_rectangle, draw_text, get_char_pressed, get_frame_time,
+    get_last_key_pressed, is_key_down, is_key_pressed,
+    is_mouse_button_pressed, mouse_position, next_frame, screen_height, screen_width, Color, KeyCode,
+    MouseButton,
 };
-use macroquad::prelude::{BLACK, BLUE, GREEN, RED, WHITE, YELLOW};
+use macroquad::prelude::{BLACK, BLUE, DARKGRAY, GRAY, GREEN, RED, WHITE, YELLOW};
 
-pub const DEFAULT_PIXEL_SIZE: i32 = 20;
-const DEFAULT_SPEED_MULTIPLIER: usize = 1;
+const FRAME_DURATION: f32 = 1.0 / TIMER_FREQUENCY as f32;
+// frames run per host frame before we give up catching up (e.g. after the window was dragged)
+const MAX_FRAMES_PER_UPDATE: u32 = 4;
 const DEFAULT_ROM_FILENAME: &str = "./roms/chippi.ch8";
-const BUZZ1: &str = "./audio/buzz1.wav";
-const BUZZ2: &str = "./audio/buzz2.wav";
-const BUZZ3: &str = "./audio/buzz3.wav";
+const SAVE_SLOT_COUNT: u8 = 4;
+const REWIND_SECONDS: usize = 60;
+// seconds a status message stays on screen
+const STATUS_DURATION: f32 = 2.0;
+const BUZZ_FILENAMES: [&str; 3] = ["buzz1.wav", "buzz2.wav", "buzz3.wav"];
 const SOUND_PARAMS: PlaySoundParams = PlaySoundParams {
     looped: false,
     volume: 0.5,
 };
+const LOOPED_SOUND_PARAMS: PlaySoundParams = PlaySoundParams {
+    looped: true,
+    volume: 1.0,
+};
+// length of the looped buffers the beeper and xo-chip audio patterns are rendered into
+const LOOP_BUFFER_DURATION: f32 = 0.25;
+const PATTERN_VOLUME: f32 = 0.5;
+// macroquad can't unload a sound, so this many xo-chip patterns are kept for good (about 90kb
+// each) and any after them play the beeper
+const MAX_PATTERN_SOUNDS: usize = 64;
+// share of the window the game keeps while the debugger is open
+const DEBUGGER_GAME_WIDTH: f32 = 0.5;
+const DEBUGGER_ROWS: usize = 32;
+const DISASSEMBLY_LINES: usize = 16;
+// rows above the disassembly: registers, I/PC/SP, timers, stack, run state and a gap
+const DISASSEMBLY_TOP_ROW: usize = 9;
+// below the disassembly: watchpoints, conditions, then the command line on the last row
+const WATCH_ROW: usize = DISASSEMBLY_TOP_ROW + DISASSEMBLY_LINES + 1;
+const CONDITION_ROWS: usize = 3;
+// the keys overlay lays the chip-8 keys out like the cosmac vip keypad
+const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
 
 pub enum JSEvents{
     ChangeColor(i32),
     SwapRom(String),
     ChangeSpeed(i32),
     ChangeRainbowMode(i32),
+    // a Computer id, or -1 to go by the rom
+    ChangeComputer(i32),
 }
 
+// named by cli::PALETTE_NAMES, in the same order
 const ALL_COLORS: [Color; 5] = [GREEN, RED, WHITE, BLUE, YELLOW];
 
+// everything picked on the command line
+pub struct Options {
+    pub rom_filename: String,
+    // None for what the rom database says
+    pub ips: Option<u32>,
+    // None for what the config says
+    pub speed_multiplier: Option<usize>,
+    pub tone: audio::Tone,
+    pub buzz_sounds: Option<[Sound; 3]>,
+    // an index into ALL_COLORS and whether rainbow mode is on. None for what the config says
+    pub palette: Option<(usize, bool)>,
+    pub mute: bool,
+    // None to go by the rom, see Computer::detect
+    pub computer: Option<Computer>,
+    // None for what the computer expects
+    pub quirks: Option<Quirks>,
+    pub seed: u64,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+}
+
+// where the keys overlay is at: the position on KEYPAD picked, and whether the next key pressed
+// is to be bound to it
+#[derive(Clone, Copy)]
+struct KeysOverlay {
+    selected: usize,
+    listening: bool,
+}
+
 pub struct Program {
     chip: Chip8,
+    rom_filename: String,
+    save_slot: u8,
+    rewind: RewindBuffer,
+    computer: Computer,
+    quirks: Quirks,
+    // what was picked on the command line or in the web page, kept when the rom is swapped
+    computer_choice: Option<Computer>,
+    quirks_choice: Option<Quirks>,
+    ips_choice: Option<u32>,
+    speed_choice: Option<usize>,
+    palette_choice: Option<(usize, bool)>,
+    config: Config,
+    // the key of the rom's section in the config
+    rom_hash: String,
+    seed: u64,
+    ips: u32,
     speed_multiplier: usize,
+    frame_time: f32,
     color: usize,
-    sound: [Sound; 3],
+    tone_sound: Option<Sound>,
+    // recordings replacing the beeper, picked by how long the sound timer was set for
+    buzz_sounds: Option<[Sound; 3]>,
+    // xo-chip patterns are rendered once per pattern and pitch, then looped
+    pattern_sounds: HashMap<([u8; AUDIO_PATTERN_SIZE], u8), Sound>,
+    playing: Option<Sound>,
     latch: bool,
     rainbow_mode: bool,
+    mute: bool,
     frame_counter: u8,
+    halted: Option<Chip8Error>,
+    status: Option<(String, f32)>,
+    debugger: Debugger,
+    debugging: bool,
+    // the debugger command being typed, the game gets no input meanwhile
+    prompt: Option<String>,
+    key_map: KeyMap,
+    // the game is paused while the keys are being changed
+    keys_overlay: Option<KeysOverlay>,
+    // while a movie is recorded or replayed, anything that would make the run diverge is locked
+    recorder: Option<BufWriter<File>>,
+    replay: Option<std::vec::IntoIter<u16>>,
 }
 
 impl Program {
-    pub async fn init(
-        rom_filename: String,
-        speed_multiplier: usize,
-        sound: [Sound; 3],
-        rainbow_mode: bool,
-    ) -> Program {
-        let chip = Chip8::from_rom(Computer::Normal, rom_filename.clone()).await;
+    pub async fn init(mut options: Options, config: Config) -> Program {
+        let data = load_rom_file(&options.rom_filename).await;
+        let rom_hash = movie::rom_hash_hex(&data);
+        let settings = config.rom_settings(&rom_hash);
+        let (rom, mut computer) =
+            compile_rom(&options.rom_filename, data, options.computer.or(settings.computer));
+        let mut quirks =
+            options.quirks.or(settings.quirks).unwrap_or_else(|| computer.default_quirks());
+        let mut ips = options.ips.or(settings.ips).unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND);
+        let mut speed_multiplier = options.speed_multiplier.or(settings.speed).unwrap_or(1);
+        // the demo shows off rainbow mode unless a palette was picked
+        let (color, rainbow_mode) = options
+            .palette
+            .or(settings.palette)
+            .unwrap_or((0, options.rom_filename == DEFAULT_ROM_FILENAME));
+
+        let mut replay = None;
+        if let Some(filename) = &options.replay {
+            let movie = fs::read(filename)
+                .map_err(|e| e.to_string())
+                .and_then(|data| Movie::parse(&data).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| fail(format!("Couldn't load replay {filename}: {e}")));
+            if movie.header.rom_hash != movie::rom_hash(&rom) {
+                fail(format!("Replay {filename} was recorded with a different rom"));
+            }
+            computer = movie.header.computer;
+            quirks = movie.header.quirks;
+            options.seed = movie.header.seed;
+            ips = movie.header.ips;
+            speed_multiplier = 1;
+            replay = Some(movie.frames.into_iter());
+        }
+
+        let mut recorder = None;
+        if let Some(filename) = &options.record {
+            // the speed the chip will really run at, so the movie replays
+            let range = INSTRUCTIONS_PER_SECOND_RANGE;
+            ips = ips.saturating_mul(speed_multiplier as u32).clamp(*range.start(), *range.end());
+            speed_multiplier = 1;
+            let header = MovieHeader {
+                rom_hash: movie::rom_hash(&rom),
+                computer,
+                quirks,
+                ips,
+                seed: options.seed,
+            };
+            let mut file = File::create(filename)
+                .map(BufWriter::new)
+                .unwrap_or_else(|e| fail(format!("Couldn't create recording {filename}: {e}")));
+            file.write_all(&header.to_bytes())
+                .unwrap_or_else(|e| fail(format!("Couldn't write recording {filename}: {e}")));
+            recorder = Some(file);
+        }
+
+        let chip = new_chip(computer, quirks, options.seed, &rom);
+        let key_map = config.key_map(&rom_hash);
+        let mut rewind = RewindBuffer::new(REWIND_SECONDS * TIMER_FREQUENCY as usize);
+        rewind.push(chip.save_state());
 
         Program {
             chip,
+            rom_filename: options.rom_filename,
+            save_slot: 1,
+            rewind,
+            computer,
+            quirks,
+            computer_choice: options.computer,
+            quirks_choice: options.quirks,
+            ips_choice: options.ips,
+            speed_choice: options.speed_multiplier,
+            palette_choice: options.palette,
+            config,
+            rom_hash,
+            seed: options.seed,
+            ips,
             speed_multiplier,
-            sound,
-            color: 0,
+            frame_time: 0.0,
+            tone_sound: load_sound_from_bytes(&audio::encode_wav(&audio::render_tone(
+                &options.tone,
+                LOOP_BUFFER_DURATION,
+            )))
+            .await
+            .ok(),
+            buzz_sounds: options.buzz_sounds,
+            pattern_sounds: HashMap::new(),
+            playing: None,
+            color,
             rainbow_mode,
+            mute: options.mute,
             latch: true,
             frame_counter: 0,
+            halted: None,
+            status: None,
+            debugger: Debugger::new(),
+            debugging: false,
+            prompt: None,
+            key_map,
+            keys_overlay: None,
+            recorder,
+            replay,
+        }
+    }
+
+    fn movie_active(&self) -> bool {
+        self.recorder.is_some() || self.replay.is_some()
+    }
+
+    // also restarts the current rom after the computer was changed.
+    // the speed and palette stay as they are unless picked, in the config or in the rom database
+    async fn swap_rom(&mut self, rom_filename: String) {
+        let data = load_rom_file(&rom_filename).await;
+        self.rom_hash = movie::rom_hash_hex(&data);
+        let settings = self.config.rom_settings(&self.rom_hash);
+        let (rom, computer) =
+            compile_rom(&rom_filename, data, self.computer_choice.or(settings.computer));
+        self.computer = computer;
+        self.quirks =
+            self.quirks_choice.or(settings.quirks).unwrap_or_else(|| computer.default_quirks());
+        self.key_map = self.config.key_map(&self.rom_hash);
+        self.ips = self.ips_choice.or(settings.ips).unwrap_or(DEFAULT_INSTRUCTIONS_PER_SECOND);
+        if let Some(speed) = self.speed_choice.or(settings.speed) {
+            self.speed_multiplier = speed;
         }
+        if let Some((color, rainbow_mode)) = self.palette_choice.or(settings.palette) {
+            self.color = color;
+            self.rainbow_mode = rainbow_mode;
+        }
+        self.chip = new_chip(self.computer, self.quirks, self.seed, &rom);
+        self.rom_filename = rom_filename;
+        self.rewind.clear();
+        self.rewind.push(self.chip.save_state());
+        self.frame_time = 0.0;
+        self.halted = None;
     }
 
     pub async fn run(&mut self, events: &mut Option<VecDeque<JSEvents>>) -> bool {
         while self.process_sys_input() {
 
             // processing js events
-            match events {
-                Some(events) => {
-                    while let Some(ev) = events.pop_front() {
-                        match ev {
-                            JSEvents::ChangeColor(new_color) => {
+            if let Some(events) = events {
+                while let Some(ev) = events.pop_front() {
+                    match ev {
+                        JSEvents::ChangeColor(new_color) => {
+                            self.color = new_color as usize;
+                        },
+                        JSEvents::SwapRom(_) if self.movie_active() => {}
+                        JSEvents::SwapRom(rom_filename) => self.swap_rom(rom_filename).await,
+                        JSEvents::ChangeSpeed(_) if self.movie_active() => {}
+                        JSEvents::ChangeSpeed(new_speed) => {
+                            self.speed_multiplier = new_speed as usize;
+                        },
+                        JSEvents::ChangeRainbowMode(new_color) => {
+                            if self.rainbow_mode {
                                 self.color = new_color as usize;
-                            },
-                            JSEvents::SwapRom(rom_filename) => {
-                                self.chip = Chip8::from_rom(Computer::Normal, rom_filename).await;
-                            },
-                            JSEvents::ChangeSpeed(new_speed) => {
-                                self.speed_multiplier = new_speed as usize;
-                            },
-                            JSEvents::ChangeRainbowMode(new_color) => {
-                                if self.rainbow_mode {
-                                    self.color = new_color as usize;
-                                }
-                                self.rainbow_mode = !self.rainbow_mode;
                             }
+                            self.rainbow_mode = !self.rainbow_mode;
+                        }
+                        JSEvents::ChangeComputer(_) if self.movie_active() => {}
+                        JSEvents::ChangeComputer(id) => {
+                            self.computer_choice = u8::try_from(id).ok().and_then(Computer::from_id);
+                            self.swap_rom(self.rom_filename.clone()).await;
                         }
                     }
                 }
-                _ => {}
             }
 
             self.frame_counter = self.frame_counter.wrapping_add(1);
             clear_background(BLACK);
-            for _ in 0..self.speed_multiplier {
+            if self.prompt.is_some() || self.keys_overlay.is_some() {
+                self.chip.kb = [0; 16];
+            } else {
                 self.fill_chip_input();
-                self.chip.tick();
-                self.process_audio();
+            }
+            if self.debugging {
+                self.process_debugger_input();
+            }
+            let rewinding = is_key_down(KeyCode::Backspace) && self.prompt.is_none();
+            if self.keys_overlay.is_some() {
+                self.frame_time = 0.0;
+            } else if rewinding && !self.movie_active() {
+                self.rewind_frame();
+            } else {
+                self.run_chip_frames();
+            }
+            // nothing runs while the keys are changed or the debugger is paused, the beeper included
+            if self.keys_overlay.is_some() || self.debugging && self.debugger.is_paused() {
+                self.stop_looped_sound();
+            } else if !self.mute {
+                self.process_audio().await;
             }
             self.draw_chip8_display();
+            if self.debugging {
+                self.draw_debugger();
+            }
+            self.draw_keys_overlay();
+            self.draw_error();
+            self.draw_status();
             next_frame().await
         }
         false
     }
 
+    // the chip runs in fixed 60hz frames no matter how fast the host redraws,
+    // so the timers always count down at the speed the games were written for
+    fn run_chip_frames(&mut self) {
+        if self.halted.is_some() || self.chip.has_exited() {
+            return;
+        }
+        self.chip
+            .set_instructions_per_second(self.ips.saturating_mul(self.speed_multiplier as u32));
+
+        if self.debugging && self.debugger.is_paused() {
+            self.frame_time = 0.0;
+            return;
+        }
+
+        self.frame_time += get_frame_time();
+        let mut frames = 0;
+        while self.frame_time >= FRAME_DURATION {
+            if frames == MAX_FRAMES_PER_UPDATE {
+                self.frame_time = 0.0;
+                break;
+            }
+            self.movie_frame();
+            let result = if self.debugging {
+                let debugger = &mut self.debugger;
+                self.chip.run_frame_until(|chip| debugger.should_break(chip))
+            } else {
+                self.chip.run_frame().map(|_| false)
+            };
+            match result {
+                Ok(false) => {}
+                Ok(true) => {
+                    self.frame_time = 0.0;
+                    break;
+                }
+                Err(e) => {
+                    self.halted = Some(e);
+                    self.chip.sreg = 0;
+                    return;
+                }
+            }
+            self.rewind.push(self.chip.save_state());
+            self.frame_time -= FRAME_DURATION;
+            frames += 1;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.flush() {
+                self.recorder = None;
+                self.show_status(format!("Recording stopped: {e}"));
+            }
+        }
+    }
+
+    // feeds the next recorded keyboard into the chip, or records the live one
+    fn movie_frame(&mut self) {
+        if let Some(frames) = &mut self.replay {
+            match frames.next() {
+                Some(keys) => self.chip.kb = movie::bits_to_keys(keys),
+                None => {
+                    self.replay = None;
+                    self.show_status("Replay finished".to_string());
+                }
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            let keys = movie::keys_to_bits(&self.chip.kb);
+            if let Err(e) = recorder.write_all(&keys.to_le_bytes()) {
+                self.recorder = None;
+                self.show_status(format!("Recording stopped: {e}"));
+            }
+        }
+    }
+
+    // one frame back per host frame while the key is held. works after a crash too, to see what led up to it
+    fn rewind_frame(&mut self) {
+        if let Some(state) = self.rewind.pop() {
+            if self.chip.load_state(&state).is_ok() {
+                self.halted = None;
+            }
+        }
+        self.frame_time = 0.0;
+    }
+
+    // the last frame stays on screen under the message so the crash can be inspected
+    fn draw_error(&self) {
+        let message = match &self.halted {
+            Some(error) => format!("Halted: {error}"),
+            None if self.chip.has_exited() => "Program exited".to_string(),
+            None => return,
+        };
+        let font_size = screen_width() / 30.0;
+        draw_rectangle(0.0, 0.0, screen_width(), font_size * 1.5, BLACK);
+        draw_text(&message, font_size * 0.25, font_size, font_size, WHITE);
+    }
+
+    fn show_status(&mut self, message: String) {
+        self.status = Some((message, STATUS_DURATION));
+    }
+
+    fn draw_status(&mut self) {
+        if let Some((message, time_left)) = &mut self.status {
+            let font_size = screen_width() / 30.0;
+            let y = screen_height() - font_size * 0.5;
+            draw_text(message, font_size * 0.25, y, font_size, WHITE);
+            *time_left -= get_frame_time();
+            if *time_left <= 0.0 {
+                self.status = None;
+            }
+        }
+    }
+
+    // states live next to the rom, e.g. roms/pong.ch8.state1
+    fn state_filename(&self) -> String {
+        format!("{}.state{}", self.rom_filename, self.save_slot)
+    }
+
+    fn save_state(&mut self) {
+        let message = match fs::write(self.state_filename(), self.chip.save_state()) {
+            Ok(()) => format!("Saved slot {}", self.save_slot),
+            Err(e) => format!("Couldn't save slot {}: {e}", self.save_slot),
+        };
+        self.show_status(message);
+    }
+
+    fn load_state(&mut self) {
+        let result = fs::read(self.state_filename())
+            .map_err(|e| e.to_string())
+            .and_then(|state| self.chip.load_state(&state).map_err(|e| e.to_string()));
+        let message = match result {
+            Ok(()) => {
+                self.halted = None;
+                self.frame_time = 0.0;
+                format!("Loaded slot {}", self.save_slot)
+            }
+            Err(e) => format!("Couldn't load slot {}: {e}", self.save_slot),
+        };
+        self.show_status(message);
+    }
+
     fn increase_color(&mut self) {
         self.color += 1;
         if self.color > ALL_COLORS.len() - 1 {
@@ -109,17 +506,69 @@ impl Program {
         }
     }
 
+    // what the hotkeys change is kept in the rom's section of the config
+    fn save_setting(&mut self, change: impl FnOnce(&mut Settings)) {
+        let name = Path::new(&self.rom_filename)
+            .file_name()
+            .map_or_else(|| self.rom_filename.clone(), |name| name.to_string_lossy().into_owned());
+        change(self.config.rom_section(&self.rom_hash, &name));
+        if let Err(e) = self.config.save() {
+            self.show_status(format!("Couldn't save the settings: {e}"));
+        }
+    }
+
     fn process_sys_input(&mut self) -> bool {
-        if is_key_pressed(KeyCode::Key9) {
-            if self.speed_multiplier < 20 {
-                self.speed_multiplier += 1;
-            }
+        if self.prompt.is_some() {
+            self.process_prompt_input();
+            return true;
         }
-        if is_key_pressed(KeyCode::Key8) {
+        if self.keys_overlay.is_some() {
+            self.process_keys_overlay_input();
+            return true;
+        }
+        let movie_active = self.movie_active();
+        let speed_multiplier = self.speed_multiplier;
+        if is_key_pressed(KeyCode::Key9) && self.speed_multiplier < 20 && !movie_active {
+            self.speed_multiplier += 1;
+        }
+        if is_key_pressed(KeyCode::Key8) && !movie_active {
             self.speed_multiplier = self.speed_multiplier.saturating_sub(1);
         }
+        // a speed of 0 pauses, which isn't worth keeping
+        if self.speed_multiplier != speed_multiplier && self.speed_multiplier > 0 {
+            let speed = self.speed_multiplier;
+            self.save_setting(|settings| settings.speed = Some(speed));
+        }
         if is_key_pressed(KeyCode::Key0) {
-            self.increase_color()
+            self.increase_color();
+            let palette = config::palette_name(self.color, self.rainbow_mode);
+            self.save_setting(|settings| settings.palette = Some(palette));
+        }
+        if is_key_pressed(KeyCode::F5) {
+            self.save_state();
+        }
+        if is_key_pressed(KeyCode::F6) {
+            self.save_slot = self.save_slot % SAVE_SLOT_COUNT + 1;
+            self.show_status(format!("Slot {}", self.save_slot));
+        }
+        if is_key_pressed(KeyCode::F7) && !movie_active {
+            self.load_state();
+        }
+        // the debugger changes where frames end, which a movie can't reproduce
+        if is_key_pressed(KeyCode::F1) && !movie_active {
+            self.debugging = !self.debugging;
+            self.debugger.pause();
+        }
+        if is_key_pressed(KeyCode::F2) {
+            self.keys_overlay = Some(KeysOverlay {
+                selected: 0,
+                listening: false,
+            });
+        }
+        if is_key_pressed(KeyCode::Enter) && self.debugging {
+            // characters typed while playing are still queued up
+            while get_char_pressed().is_some() {}
+            self.prompt = Some(String::new());
         }
         if is_key_pressed(KeyCode::Escape) {
             return false;
@@ -127,31 +576,288 @@ impl Program {
         true
     }
 
+    fn process_keys_overlay_input(&mut self) {
+        let Some(mut overlay) = self.keys_overlay else {
+            return;
+        };
+        let chip_key = KEYPAD[overlay.selected];
+        if overlay.listening {
+            // Escape changes its mind, anything else is bound
+            let Some(key) = get_last_key_pressed() else {
+                return;
+            };
+            overlay.listening = false;
+            self.keys_overlay = Some(overlay);
+            if key == KeyCode::Escape {
+                return;
+            }
+            match self.key_map.bind(chip_key, key) {
+                Ok(()) => self.save_key_map(),
+                Err(e) => self.show_status(e),
+            }
+            return;
+        }
+
+        let (row, col) = (overlay.selected / 4, overlay.selected % 4);
+        if is_key_pressed(KeyCode::Left) {
+            overlay.selected = row * 4 + (col + 3) % 4;
+        }
+        if is_key_pressed(KeyCode::Right) {
+            overlay.selected = row * 4 + (col + 1) % 4;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            overlay.selected = (row + 3) % 4 * 4 + col;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            overlay.selected = (row + 1) % 4 * 4 + col;
+        }
+        overlay.listening = is_key_pressed(KeyCode::Enter);
+        self.keys_overlay = Some(overlay);
+        if is_key_pressed(KeyCode::Delete) {
+            self.key_map.clear(chip_key);
+            self.save_key_map();
+        }
+        // back to the layout the rom gets without its own
+        if is_key_pressed(KeyCode::Home) {
+            self.save_setting(|settings| settings.keys = None);
+            self.key_map = self.config.key_map(&self.rom_hash);
+        }
+        if is_key_pressed(KeyCode::F2) || is_key_pressed(KeyCode::Escape) {
+            self.keys_overlay = None;
+        }
+    }
+
+    // the rom keeps the whole layout, so changing the defaults later leaves it alone
+    fn save_key_map(&mut self) {
+        let keys = self.key_map.to_table();
+        self.save_setting(|settings| settings.keys = Some(keys));
+    }
+
+    fn process_prompt_input(&mut self) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+        // the queue hands out the newest character first
+        let mut typed: Vec<char> = std::iter::from_fn(get_char_pressed).collect();
+        typed.reverse();
+        prompt.extend(typed.into_iter().filter(|c| !c.is_control()));
+        if is_key_pressed(KeyCode::Backspace) {
+            prompt.pop();
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.prompt = None;
+        } else if is_key_pressed(KeyCode::Enter) {
+            let line = self.prompt.take().unwrap_or_default();
+            let message = match self.debugger.command(&line, &self.chip) {
+                Ok(message) | Err(message) => message,
+            };
+            self.show_status(message);
+        }
+    }
+
+    fn process_debugger_input(&mut self) {
+        if self.halted.is_some() || self.chip.has_exited() {
+            return;
+        }
+        if is_key_pressed(KeyCode::F8) {
+            if self.debugger.is_paused() {
+                self.debugger.resume(&self.chip);
+            } else {
+                self.debugger.pause();
+            }
+        }
+        let stepped = if is_key_pressed(KeyCode::F11) {
+            Some(self.debugger.step(&mut self.chip))
+        } else if is_key_pressed(KeyCode::F10) {
+            Some(self.debugger.step_over(&mut self.chip))
+        } else {
+            None
+        };
+        if let Some(Err(e)) = stepped {
+            self.halted = Some(e);
+            self.chip.sreg = 0;
+        }
+        if is_key_pressed(KeyCode::F12) {
+            self.debugger.run_to_return(&self.chip);
+        }
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (x, y) = mouse_position();
+            let row = (y / self.debugger_row_height()) as usize;
+            if x >= self.debugger_x() && row >= DISASSEMBLY_TOP_ROW {
+                if let Some((addr, _)) = self.disassembly().get(row - DISASSEMBLY_TOP_ROW) {
+                    self.debugger.toggle_breakpoint(*addr);
+                }
+            }
+        }
+    }
+
+    fn debugger_x(&self) -> f32 {
+        screen_width() * DEBUGGER_GAME_WIDTH
+    }
+
+    fn debugger_row_height(&self) -> f32 {
+        screen_height() / DEBUGGER_ROWS as f32
+    }
+
+    // a few instructions before pc then the ones after it. going backwards is a guess,
+    // the bytes before pc could be data or the tail of a long load
+    fn disassembly(&self) -> Vec<(u16, String)> {
+        let mut addr = (self.chip.pc() as usize).saturating_sub(8);
+        let mut lines = Vec::with_capacity(DISASSEMBLY_LINES);
+        while lines.len() < DISASSEMBLY_LINES && addr < self.chip.ram.len() {
+            let (text, len) = disasm::disassemble(&self.chip.ram, addr, self.chip.computer());
+            lines.push((addr as u16, text));
+            addr += len;
+        }
+        lines
+    }
+
+    fn draw_debugger(&self) {
+        let x = self.debugger_x();
+        let row_height = self.debugger_row_height();
+        let text_x = x + row_height * 0.5;
+        draw_rectangle(x, 0.0, screen_width() - x, screen_height(), DARKGRAY);
+        let row_y = |row: usize| (row + 1) as f32 * row_height - row_height * 0.2;
+
+        let regs = self.chip.regs();
+        for row in 0..4 {
+            let line = (0..4)
+                .map(|col| {
+                    let reg = row * 4 + col;
+                    format!("V{reg:X} {:02X}", regs[reg])
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            draw_text(&line, text_x, row_y(row), row_height, WHITE);
+        }
+        let pointers = format!(
+            "I {:04X}  PC {:04X}  SP {}",
+            self.chip.ireg(),
+            self.chip.pc(),
+            self.chip.sp()
+        );
+        draw_text(&pointers, text_x, row_y(4), row_height, WHITE);
+        let timers = format!("DT {:02X}  ST {:02X}", self.chip.dreg(), self.chip.sreg);
+        draw_text(&timers, text_x, row_y(5), row_height, WHITE);
+        let stack = self
+            .chip
+            .stack()
+            .iter()
+            .map(|addr| format!("{addr:04X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        draw_text(&format!("Stack {stack}"), text_x, row_y(6), row_height, WHITE);
+        let state = match self.debugger.reason() {
+            Some(reason) if self.debugger.is_paused() => reason,
+            _ if self.debugger.is_paused() => "Paused",
+            _ => "Running",
+        };
+        draw_text(state, text_x, row_y(7), row_height, GRAY);
+
+        for (i, (addr, text)) in self.disassembly().iter().enumerate() {
+            let row = DISASSEMBLY_TOP_ROW + i;
+            if *addr == self.chip.pc() {
+                draw_rectangle(x, row as f32 * row_height, screen_width() - x, row_height, BLACK);
+            }
+            let marker = if self.debugger.breakpoints().contains(addr) { "*" } else { " " };
+            let color = if *addr == self.chip.pc() { YELLOW } else { WHITE };
+            let line = format!("{marker} {addr:04X}  {text}");
+            draw_text(&line, text_x, row_y(row), row_height, color);
+        }
+
+        let watches = self
+            .debugger
+            .watchpoints()
+            .iter()
+            .map(|w| format!("{}:{:04X}", &w.kind.name()[..1], w.addr))
+            .collect::<Vec<_>>()
+            .join(" ");
+        draw_text(&format!("Watch {watches}"), text_x, row_y(WATCH_ROW), row_height, WHITE);
+        for (i, condition) in self.debugger.conditions().take(CONDITION_ROWS).enumerate() {
+            let line = format!("When {condition}");
+            draw_text(&line, text_x, row_y(WATCH_ROW + 1 + i), row_height, WHITE);
+        }
+        let prompt = match &self.prompt {
+            Some(prompt) => format!("> {prompt}_"),
+            None => "Enter: break / watch / clear".to_string(),
+        };
+        draw_text(&prompt, text_x, row_y(DEBUGGER_ROWS - 1), row_height, GRAY);
+    }
+
     fn fill_chip_input(&mut self) {
-        self.chip.kb[0x0] = is_key_down(KeyCode::X) as u8;
-        self.chip.kb[0x1] = is_key_down(KeyCode::Key1) as u8;
-        self.chip.kb[0x2] = is_key_down(KeyCode::Key2) as u8;
-        self.chip.kb[0x3] = is_key_down(KeyCode::Key3) as u8;
-        self.chip.kb[0x4] = is_key_down(KeyCode::Q) as u8;
-        self.chip.kb[0x5] = is_key_down(KeyCode::W) as u8;
-        self.chip.kb[0x6] = is_key_down(KeyCode::E) as u8;
-        self.chip.kb[0x7] = is_key_down(KeyCode::A) as u8;
-        self.chip.kb[0x8] = is_key_down(KeyCode::S) as u8;
-        self.chip.kb[0x9] = is_key_down(KeyCode::D) as u8;
-        self.chip.kb[0xA] = is_key_down(KeyCode::Z) as u8;
-        self.chip.kb[0xB] = is_key_down(KeyCode::C) as u8;
-        self.chip.kb[0xC] = is_key_down(KeyCode::Key4) as u8;
-        self.chip.kb[0xD] = is_key_down(KeyCode::R) as u8;
-        self.chip.kb[0xE] = is_key_down(KeyCode::F) as u8;
-        self.chip.kb[0xF] = is_key_down(KeyCode::V) as u8;
-    }
-
-    fn process_audio(&mut self) {
+        self.key_map.fill(&mut self.chip.kb);
+    }
+
+    // the chip-8 keys in a 4x4 grid, each with the host keys that press it
+    fn draw_keys_overlay(&self) {
+        let Some(overlay) = self.keys_overlay else {
+            return;
+        };
+        let font_size = screen_width() / 40.0;
+        let line_height = font_size * 1.5;
+        let cell_width = screen_width() / 4.0;
+        let cell_height = (screen_height() - line_height) / 4.0;
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.85));
+        let help = "Keys: arrows pick one, Enter adds a host key, Delete clears, Home resets, \
+                    F2 closes";
+        draw_text(help, font_size * 0.5, font_size, font_size * 0.75, GRAY);
+
+        for (i, chip_key) in KEYPAD.iter().enumerate() {
+            let x = (i % 4) as f32 * cell_width;
+            let y = line_height + (i / 4) as f32 * cell_height;
+            if i == overlay.selected {
+                draw_rectangle(x, y, cell_width, cell_height, DARKGRAY);
+            }
+            let color = if i == overlay.selected { YELLOW } else { WHITE };
+            let label = format!("{chip_key:X}");
+            draw_text(&label, x + font_size * 0.5, y + font_size * 2.0, font_size * 2.0, color);
+            let keys = match self.key_map.host_keys(*chip_key) {
+                _ if overlay.listening && i == overlay.selected => vec!["Press a key".to_string()],
+                [] => vec!["-".to_string()],
+                keys => keys.iter().map(|key| keymap::key_name(*key)).collect(),
+            };
+            for (line, key) in keys.iter().enumerate() {
+                let key_y = y + font_size * 3.5 + line as f32 * font_size;
+                draw_text(key, x + font_size * 0.5, key_y, font_size, color);
+            }
+        }
+    }
+
+    async fn process_audio(&mut self) {
+        let looped = if self.chip.computer() == Computer::XoChip {
+            self.pattern_sound().await
+        } else if let Some(buzz_sounds) = self.buzz_sounds {
+            self.process_buzz_audio(buzz_sounds);
+            return;
+        } else {
+            self.tone_sound
+        };
+
+        // looped sounds play for exactly as long as the sound timer runs
+        if self.chip.sreg == 0 {
+            self.stop_looped_sound();
+        } else if self.playing != looped {
+            self.stop_looped_sound();
+            if let Some(sound) = looped {
+                play_sound(sound, LOOPED_SOUND_PARAMS);
+                self.playing = Some(sound);
+            }
+        }
+    }
+
+    fn stop_looped_sound(&mut self) {
+        if let Some(playing) = self.playing.take() {
+            stop_sound(playing);
+        }
+    }
+
+    fn process_buzz_audio(&mut self, buzz_sounds: [Sound; 3]) {
         if self.latch && self.chip.sreg > 0 {
             match self.chip.sreg {
-                10.. => play_sound(self.sound[2], SOUND_PARAMS),
-                4..=9 => play_sound(self.sound[1], SOUND_PARAMS),
-                _ => play_sound(self.sound[0], SOUND_PARAMS),
+                10.. => play_sound(buzz_sounds[2], SOUND_PARAMS),
+                4..=9 => play_sound(buzz_sounds[1], SOUND_PARAMS),
+                _ => play_sound(buzz_sounds[0], SOUND_PARAMS),
             }
             self.latch = false;
         } else if self.chip.sreg == 0 {
@@ -159,29 +865,55 @@ impl Program {
         }
     }
 
-    fn get_color(&mut self) -> Color {
-        if self.rainbow_mode {
-            if self.frame_counter % 10 == 0 {
-                self.increase_color();
-            }
+    // xo-chip programs supply their own waveform, rendered the first time each pattern and pitch is
+    // heard. a program that sweeps the pitch or keeps rewriting the pattern runs out of room for
+    // them and gets the beeper instead
+    async fn pattern_sound(&mut self) -> Option<Sound> {
+        let key = (*self.chip.audio_pattern(), self.chip.pitch());
+        let room = self.pattern_sounds.len() < MAX_PATTERN_SOUNDS;
+        if self.chip.sreg > 0 && room && !self.pattern_sounds.contains_key(&key) {
+            let samples =
+                audio::render_pattern(&key.0, key.1, PATTERN_VOLUME, LOOP_BUFFER_DURATION);
+            let sound = load_sound_from_bytes(&audio::encode_wav(&samples)).await.ok()?;
+            self.pattern_sounds.insert(key, sound);
         }
-        ALL_COLORS[self.color]
+        self.pattern_sounds.get(&key).copied().or(self.tone_sound)
     }
 
-    fn draw_chip8_display(&mut self) {
-        let color = self.get_color();
-        for y in 0..DISPLAY_HEIGHT as usize {
-            for x in 0..DISPLAY_WIDTH as usize {
-                if self.chip.display[y * DISPLAY_WIDTH as usize + x] != 0 {
-                    let sw = screen_width() as usize;
-                    let pixel_size = sw / DISPLAY_WIDTH as usize;
+    // colours for a pixel lit on plane 1, plane 2 and both planes. only xo-chip programs use the last two
+    // is_multiple_of would need rust 1.87
+    #[allow(clippy::manual_is_multiple_of)]
+    fn get_palette(&mut self) -> [Color; 3] {
+        if self.rainbow_mode && self.frame_counter % 10 == 0 {
+            self.increase_color();
+        }
+        [
+            ALL_COLORS[self.color],
+            ALL_COLORS[(self.color + 1) % ALL_COLORS.len()],
+            ALL_COLORS[(self.color + 2) % ALL_COLORS.len()],
+        ]
+    }
 
+    fn draw_chip8_display(&mut self) {
+        let palette = self.get_palette();
+        let width = self.chip.display_width();
+        let height = self.chip.display_height();
+        let area_width = if self.debugging {
+            screen_width() * DEBUGGER_GAME_WIDTH
+        } else {
+            screen_width()
+        };
+        let pixel_size = area_width / width as f32;
+        for y in 0..height {
+            for x in 0..width {
+                let planes = self.chip.display[y * width + x] as usize;
+                if planes != 0 {
                     draw_rectangle(
-                        (x * pixel_size) as f32,
-                        (y * pixel_size) as f32,
-                        pixel_size as f32,
-                        pixel_size as f32,
-                        color,
+                        x as f32 * pixel_size,
+                        y as f32 * pixel_size,
+                        pixel_size,
+                        pixel_size,
+                        palette[planes - 1],
                     );
                 }
             }
@@ -189,34 +921,89 @@ impl Program {
     }
 }
 
-pub async fn process_env_variables() -> (String, usize, [Sound; 3], bool) {
-    let args: Vec<String> = env::args().collect();
+// fills in what the command line left out and loads what the options point to
+pub async fn load_options(options: RunOptions) -> Options {
+    // without a seed every run is different. print it so a run can be reproduced
+    let seed = options.seed.unwrap_or_else(|| {
+        let seed = (date::now() * 1000.0) as u64;
+        println!("RND seed: {seed}");
+        seed
+    });
+    let buzz_sounds = match &options.buzz_dir {
+        Some(dir) => load_buzz_sounds(dir).await,
+        None => None,
+    };
 
-    let sound: [Sound; 3] = [
-        load_sound(BUZZ1).await.unwrap(),
-        load_sound(BUZZ2).await.unwrap(),
-        load_sound(BUZZ3).await.unwrap(),
-    ];
-    
-    if args.len() == 1 {
-        return (DEFAULT_ROM_FILENAME.to_string(), 1, sound, true);
+    Options {
+        rom_filename: options.rom.unwrap_or_else(|| DEFAULT_ROM_FILENAME.to_string()),
+        ips: options.ips,
+        speed_multiplier: options.speed,
+        tone: options.tone,
+        buzz_sounds,
+        palette: options.rainbow.map(|rainbow| (options.color, rainbow)),
+        mute: options.mute,
+        computer: options.computer,
+        quirks: options.quirks,
+        seed,
+        record: options.record,
+        replay: options.replay,
     }
+}
 
-    let rom_filename = match args.get(1) {
-        Some(s) => s.clone(),
-        None => DEFAULT_ROM_FILENAME.to_string(),
-    };
-    let speed_multiplier = match args.get(2) {
-        Some(s) => match s.parse::<usize>() {
-            Ok(sp) => sp,
-            _ => panic!("Speed multiplier not valid"),
-        },
-        None => DEFAULT_SPEED_MULTIPLIER,
-    };
-    let rainbow_mode = match args.get(3) {
-        Some(_s) => true,
-        None => false,
-    };
+// a bad file named on the command line only shows up once the window is open.
+// reported the same way as the mistakes cli.rs catches
+fn fail(message: String) -> ! {
+    eprintln!("chippi: {message}");
+    std::process::exit(1)
+}
+
+// the file as it's stored, which is what the config is keyed by, even for octo source
+async fn load_rom_file(rom_filename: &str) -> Vec<u8> {
+    match load_file(rom_filename).await {
+        Ok(data) => data,
+        Err(_) => fail(format!("Couldn't load {rom_filename} ({})", Chip8Error::FileNotFound)),
+    }
+}
+
+// octo source is compiled on the fly. without a computer picked, the rom decides
+fn compile_rom(
+    rom_filename: &str,
+    data: Vec<u8>,
+    computer: Option<Computer>,
+) -> (Vec<u8>, Computer) {
+    if !rom_filename.ends_with(".8o") {
+        let computer = computer.unwrap_or_else(|| Computer::detect(rom_filename, &data));
+        return (data, computer);
+    }
+    let computer = computer.unwrap_or(Computer::Normal);
+    match octo::compile(&String::from_utf8_lossy(&data), computer) {
+        Ok(rom) => (rom, computer),
+        Err(e) => fail(format!("Couldn't compile {rom_filename}: {e}")),
+    }
+}
+
+fn new_chip(computer: Computer, quirks: Quirks, seed: u64, rom: &[u8]) -> Chip8 {
+    match Chip8::from_bytes(computer, quirks, rom) {
+        Ok(mut chip) => {
+            chip.set_seed(seed);
+            chip
+        }
+        Err(e) => fail(format!("Couldn't load the rom ({e})")),
+    }
+}
 
-    (rom_filename, speed_multiplier, sound, rainbow_mode)
-}
\ No newline at end of file
+// the beeper is used instead if any of the recordings can't be loaded
+async fn load_buzz_sounds(dir: &str) -> Option<[Sound; 3]> {
+    let mut sounds = Vec::with_capacity(BUZZ_FILENAMES.len());
+    for filename in BUZZ_FILENAMES {
+        let path = format!("{}/{filename}", dir.trim_end_matches('/'));
+        match load_sound(&path).await {
+            Ok(sound) => sounds.push(sound),
+            Err(_) => {
+                eprintln!("Couldn't load {path}, using the beeper");
+                return None;
+            }
+        }
+    }
+    sounds.try_into().ok()
+}
@@ -0,0 +1,106 @@
+/// Behaviours that differ between the interpreters CHIP-8 programs were written for.
+/// A ROM that relies on one interpretation usually breaks in subtle ways under another,
+/// so these are picked per ROM rather than fixed in the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6 / 8xyE shift Vy and store the result in Vx, instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// Fx55 / Fx65 leave I pointing just past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// Bnnn jumps to nnn + Vx, where x is the highest nibble of nnn, instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    /// 8xy1 / 8xy2 / 8xy3 reset VF to 0.
+    pub vf_reset: bool,
+    /// Sprites are cut off at the edges of the screen instead of wrapping around to the other side.
+    pub clip_sprites: bool,
+    /// Dxyn waits for the next 60hz frame before execution continues. Only in low resolution,
+    /// SUPER-CHIP 1.1 draws at once in high resolution.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators, which most early 90s ROMs were written against.
+    pub const CHIP48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1, the successor of CHIP-48. It waits for the display in low resolution.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    /// What Octo and XO-CHIP do, and what most homebrew written since expects.
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+
+    pub const PRESET_NAMES: [&'static str; 4] = ["vip", "chip48", "schip", "modern"];
+
+    //packed into a byte for save states, one bit per quirk in field order
+    pub fn to_bits(&self) -> u8 {
+        (self.shift_uses_vy as u8)
+            | (self.load_store_increments_i as u8) << 1
+            | (self.jump_uses_vx as u8) << 2
+            | (self.vf_reset as u8) << 3
+            | (self.clip_sprites as u8) << 4
+            | (self.display_wait as u8) << 5
+    }
+
+    pub fn from_bits(bits: u8) -> Quirks {
+        Quirks {
+            shift_uses_vy: bits & 1 != 0,
+            load_store_increments_i: bits & 1 << 1 != 0,
+            jump_uses_vx: bits & 1 << 2 != 0,
+            vf_reset: bits & 1 << 3 != 0,
+            clip_sprites: bits & 1 << 4 != 0,
+            display_wait: bits & 1 << 5 != 0,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP48),
+            "schip" | "superchip" => Some(Quirks::SUPER_CHIP),
+            "modern" | "octo" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+}
+
+/// What chippi did before the quirks could be picked: CHIP-48's shifts and loads, but Bnnn adds V0
+/// and sprites wrap around.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            jump_uses_vx: false,
+            clip_sprites: false,
+            ..Quirks::CHIP48
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! History of save states for stepping backwards through gameplay.
+//!
+//! Only the newest state is kept whole. Every older frame is stored as the XOR of itself with the
+//! frame after it, with the runs of zeroes (the bytes that didn't change) squeezed out. Memory and
+//! the display barely change from one frame to the next, so most frames cost a few dozen bytes.
+
+use std::collections::VecDeque;
+
+const DELTA: u8 = 0;
+// states of different lengths (the computer changed) can't be xored, they are kept whole
+const FULL: u8 = 1;
+
+pub struct RewindBuffer {
+    capacity: usize,
+    deltas: VecDeque<Vec<u8>>,
+    current: Option<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// Keeps up to `capacity` frames before the newest one.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            deltas: VecDeque::with_capacity(capacity),
+            current: None,
+        }
+    }
+
+    /// Records the state of a new frame.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.current.take() {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(encode(&previous, &state));
+        }
+        self.current = Some(state);
+    }
+
+    /// Steps back one frame, returning the state before the newest one. That state becomes the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let current = self.current.as_ref()?;
+        let previous = decode(current, &delta);
+        self.current = Some(previous.clone());
+        Some(previous)
+    }
+
+    /// Number of frames that can be stepped back.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.current = None;
+    }
+}
+
+// delta layout: DELTA, then pairs of (zero run, literal run) lengths as varints, each literal run
+// followed by its bytes. the last literal run may be empty
+fn encode(previous: &[u8], next: &[u8]) -> Vec<u8> {
+    if previous.len() != next.len() {
+        let mut full = Vec::with_capacity(previous.len() + 1);
+        full.push(FULL);
+        full.extend_from_slice(previous);
+        return full;
+    }
+
+    let xored: Vec<u8> = previous.iter().zip(next).map(|(a, b)| a ^ b).collect();
+    let mut out = vec![DELTA];
+    let mut i = 0;
+    while i < xored.len() {
+        let zeroes = xored[i..].iter().take_while(|b| **b == 0).count();
+        i += zeroes;
+        let literals = xored[i..].iter().take_while(|b| **b != 0).count();
+        write_varint(&mut out, zeroes);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&xored[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+fn decode(next: &[u8], delta: &[u8]) -> Vec<u8> {
+    if delta[0] == FULL {
+        return delta[1..].to_vec();
+    }
+
+    let mut previous = next.to_vec();
+    let mut pos = 1;
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for (dst, x) in previous[i..i + literals].iter_mut().zip(&delta[pos..]) {
+            *dst ^= x;
+        }
+        pos += literals;
+        i += literals;
+    }
+    previous
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a state with a bit of everything: long runs of zeroes and of other bytes
+    fn state(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| match i % 500 {
+                0..=199 => 0,
+                200..=399 => (i as u8).wrapping_mul(seed) | 1,
+                _ => seed,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steps_back_through_what_was_pushed() {
+        let a = state(1000, 3);
+        // changes at both ends and a run of over 127 changed bytes, for the two byte varints
+        let mut b = a.clone();
+        b[0] ^= 0xFF;
+        b[999] ^= 1;
+        for byte in &mut b[300..500] {
+            *byte = !*byte;
+        }
+        let c = b.clone();
+        // a different computer, so a different length
+        let d = state(600, 5);
+        let mut e = d.clone();
+        e[10] = 42;
+
+        let mut buffer = RewindBuffer::new(10);
+        assert!(buffer.is_empty());
+        for s in [&a, &b, &c, &d, &e] {
+            buffer.push(s.clone());
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.pop(), Some(d));
+        assert_eq!(buffer.pop(), Some(c));
+        assert_eq!(buffer.pop(), Some(b.clone()));
+        // playing on after stepping back carries on from there
+        let mut f = b.clone();
+        f[500] ^= 0x80;
+        buffer.push(f);
+        assert_eq!(buffer.pop(), Some(b));
+        assert_eq!(buffer.pop(), Some(a));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn identical_states_cost_next_to_nothing() {
+        let a = state(1000, 3);
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(a.clone());
+        buffer.push(a.clone());
+        assert!(buffer.deltas[0].len() < 5);
+        assert_eq!(buffer.pop(), Some(a));
+    }
+
+    #[test]
+    fn keeps_at_most_capacity_frames() {
+        let mut buffer = RewindBuffer::new(3);
+        for seed in 0..10 {
+            buffer.push(state(100, seed));
+            assert_eq!(buffer.len(), (seed as usize).min(3));
+        }
+        assert_eq!(buffer.pop(), Some(state(100, 8)));
+        assert_eq!(buffer.pop(), Some(state(100, 7)));
+        assert_eq!(buffer.pop(), Some(state(100, 6)));
+        assert_eq!(buffer.pop(), None);
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.pop(), None);
+    }
+}
@@ -0,0 +1,49 @@
+/// Source of the random bytes used by `RND`.
+/// Swap it out with `Chip8::set_rng` to control what a program sees.
+pub trait Rng {
+    fn next_byte(&mut self) -> u8;
+    /// The generator's whole internal state, stored in save states.
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+/// Small and fast xorshift generator, the default source for `RND`.
+/// The same seed always produces the same sequence, on every platform.
+pub struct XorShiftRng {
+    state: u64,
+}
+
+pub const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> XorShiftRng {
+        //an all zero state would only ever produce zeroes
+        XorShiftRng {
+            state: if seed == 0 { DEFAULT_SEED } else { seed },
+        }
+    }
+}
+
+impl Default for XorShiftRng {
+    fn default() -> Self {
+        XorShiftRng::new(DEFAULT_SEED)
+    }
+}
+
+impl Rng for XorShiftRng {
+    fn next_byte(&mut self) -> u8 {
+        //xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        *self = XorShiftRng::new(state);
+    }
+}
@@ -0,0 +1,109 @@
+//! The binary save state format.
+//!
+//! A state starts with the magic bytes `CHPS` and a little endian u16 version, followed by the
+//! machine fields in the order `Chip8::save_state` writes them. All numbers are little endian.
+//! Bump `STATE_VERSION` whenever the layout changes; older states are rejected rather than misread.
+
+use crate::chip8::Chip8Error;
+
+pub const STATE_MAGIC: &[u8; 4] = b"CHPS";
+pub const STATE_VERSION: u16 = 1;
+
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { buf: Vec::new() };
+        writer.bytes(STATE_MAGIC);
+        writer.u16(STATE_VERSION);
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.buf.extend_from_slice(value);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, Chip8Error> {
+        let mut reader = StateReader { data, pos: 0 };
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(Chip8Error::InvalidState("not a chippi save state"));
+        }
+        if reader.u16()? != STATE_VERSION {
+            return Err(Chip8Error::InvalidState("save state version not supported"));
+        }
+        Ok(reader)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(Chip8Error::InvalidState("save state is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Chip8Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Chip8Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn finish(self) -> Result<(), Chip8Error> {
+        if self.pos != self.data.len() {
+            return Err(Chip8Error::InvalidState("save state has trailing data"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// the emulator in a terminal, for machines without a graphical session.
+// two rows of pixels share a character cell: a half block in the foreground colour draws
+// one of them, the background colour the other
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use chippi::chip8::{Chip8, TIMER_FREQUENCY};
+use macroquad::prelude::KeyCode as HostKey;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use crate::keymap::{self, KeyMap};
+
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / TIMER_FREQUENCY as u64);
+// the ansi colours closest to ALL_COLORS in program.rs, in the same order
+const ALL_COLORS: [Color; 5] = [
+    Color::Green,
+    Color::Red,
+    Color::White,
+    Color::Blue,
+    Color::Yellow,
+];
+// most terminals only report key presses, and auto repeat, so a key counts as held for this many
+// frames after it was last seen. terminals with the kitty keyboard protocol report releases too
+const KEY_HOLD_FRAMES: u32 = 8;
+
+// puts the terminal back the way it was, even when we bail out with an error or a panic
+struct RawTerminal {
+    enhanced_keys: bool,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        let enhanced_keys = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut out = io::stdout();
+        execute!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        if enhanced_keys {
+            execute!(
+                out,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(RawTerminal { enhanced_keys })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.enhanced_keys {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// color is an index into ALL_COLORS
+pub fn run(
+    mut chip: Chip8,
+    title: &str,
+    key_map: &KeyMap,
+    mut color: usize,
+    mute: bool,
+) -> io::Result<()> {
+    let raw = RawTerminal::enter()?;
+    let mut out = io::stdout();
+    // frames each key stays held for, counting down
+    let mut held = [0u32; 16];
+    let mut halted = None;
+    let mut last_display = None;
+    let mut last_sound = 0;
+    let mut next_frame = Instant::now();
+
+    loop {
+        // gather input until the frame is due
+        while let Some(timeout) = next_frame.checked_duration_since(Instant::now()) {
+            if !event::poll(timeout)? {
+                break;
+            }
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(..) => {
+                    last_display = None;
+                    continue;
+                }
+                _ => continue,
+            };
+            if is_quit(&key) {
+                return Ok(());
+            }
+            match key.code {
+                KeyCode::Char('0') if key.kind == KeyEventKind::Press => {
+                    color = (color + 1) % ALL_COLORS.len();
+                    last_display = None;
+                }
+                code => {
+                    if let Some(k) = host_key(code).and_then(|key| key_map.chip_key(key)) {
+                        held[k] = match key.kind {
+                            KeyEventKind::Release => 0,
+                            // with releases reported, a held key stays down until it is let go
+                            _ if raw.enhanced_keys => u32::MAX,
+                            _ => KEY_HOLD_FRAMES,
+                        };
+                    }
+                }
+            }
+        }
+        next_frame += FRAME_DURATION;
+        // don't try to catch up after the process was suspended
+        if next_frame < Instant::now() {
+            next_frame = Instant::now() + FRAME_DURATION;
+        }
+
+        for (kb, held) in chip.kb.iter_mut().zip(held.iter_mut()) {
+            *kb = (*held > 0) as u8;
+            *held = held.saturating_sub(1);
+        }
+        if halted.is_none() && !chip.has_exited() {
+            if let Err(e) = chip.run_frame() {
+                halted = Some(e);
+                last_display = None;
+            }
+        }
+        // the terminal bell stands in for the beeper
+        if chip.sreg > 0 && last_sound == 0 && !mute {
+            queue!(out, Print('\x07'))?;
+        }
+        last_sound = chip.sreg;
+
+        // only redraw when something changed, it's a lot of text over a slow connection
+        if last_display.as_ref() != Some(&chip.display) {
+            draw(&mut out, &chip, color)?;
+            let status = match (&halted, chip.has_exited()) {
+                (Some(e), _) => format!("{title}: {e}. Esc quits"),
+                (None, true) => format!("{title} exited. Esc quits"),
+                (None, false) => format!("{title}  Esc quits, 0 changes colour"),
+            };
+            let row = chip.display_height().div_ceil(2) as u16;
+            queue!(
+                out,
+                ResetColor,
+                MoveTo(0, row),
+                Clear(ClearType::CurrentLine),
+                Print(status)
+            )?;
+            last_display = Some(chip.display);
+        }
+        out.flush()?;
+    }
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.kind != KeyEventKind::Release
+        && (key.code == KeyCode::Esc
+            || key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+// the window's name for a key the terminal reports. shifted symbols and the keypad come through
+// as the characters they type, so only the unshifted ones are found
+fn host_key(code: KeyCode) -> Option<HostKey> {
+    let key = match code {
+        KeyCode::Char(c) if c.is_ascii_alphanumeric() => {
+            return keymap::key_from_name(&c.to_string());
+        }
+        KeyCode::Char(' ') => HostKey::Space,
+        KeyCode::Char('\'') => HostKey::Apostrophe,
+        KeyCode::Char(',') => HostKey::Comma,
+        KeyCode::Char('-') => HostKey::Minus,
+        KeyCode::Char('.') => HostKey::Period,
+        KeyCode::Char('/') => HostKey::Slash,
+        KeyCode::Char(';') => HostKey::Semicolon,
+        KeyCode::Char('=') => HostKey::Equal,
+        KeyCode::Char('[') => HostKey::LeftBracket,
+        KeyCode::Char('\\') => HostKey::Backslash,
+        KeyCode::Char(']') => HostKey::RightBracket,
+        KeyCode::Char('`') => HostKey::GraveAccent,
+        KeyCode::Left => HostKey::Left,
+        KeyCode::Right => HostKey::Right,
+        KeyCode::Up => HostKey::Up,
+        KeyCode::Down => HostKey::Down,
+        KeyCode::Home => HostKey::Home,
+        KeyCode::End => HostKey::End,
+        KeyCode::PageUp => HostKey::PageUp,
+        KeyCode::PageDown => HostKey::PageDown,
+        KeyCode::Tab => HostKey::Tab,
+        KeyCode::Insert => HostKey::Insert,
+        KeyCode::Delete => HostKey::Delete,
+        KeyCode::F(n) => return keymap::key_from_name(&format!("F{n}")),
+        _ => return None,
+    };
+    Some(key)
+}
+
+fn draw(out: &mut impl Write, chip: &Chip8, color: usize) -> io::Result<()> {
+    let width = chip.display_width();
+    let height = chip.display_height();
+    let (columns, rows) = terminal::size()?;
+    let pixel_color = |planes: u8| match planes {
+        0 => Color::Reset,
+        planes => ALL_COLORS[(color + planes as usize - 1) % ALL_COLORS.len()],
+    };
+
+    for (row, pair) in chip.display[..width * height]
+        .chunks(width * 2)
+        .enumerate()
+        .take(rows as usize)
+    {
+        queue!(out, MoveTo(0, row as u16))?;
+        let (upper, lower) = pair.split_at(width);
+        // colours are only sent when they change
+        let mut current = None;
+        for (top, bottom) in upper.iter().zip(lower).take(columns as usize) {
+            // the foreground is only ever a lit pixel, the terminal's own text colour never shows
+            let (c, fg, bg) = match (top & 3, bottom & 3) {
+                (0, 0) => (' ', Color::Reset, Color::Reset),
+                (0, bottom) => ('▄', pixel_color(bottom), Color::Reset),
+                (top, bottom) => ('▀', pixel_color(top), pixel_color(bottom)),
+            };
+            if current != Some((fg, bg)) {
+                queue!(out, SetForegroundColor(fg), SetBackgroundColor(bg))?;
+                current = Some((fg, bg));
+            }
+            queue!(out, Print(c))?;
+        }
+        queue!(out, ResetColor)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! The bundled ROM database: it has to parse, and know about every bundled ROM.
+
+use std::fs;
+
+use chippi::database::{self, Database};
+use chippi::{Computer, Quirks};
+
+#[test]
+fn bundled_roms_are_known() {
+    let database = Database::bundled();
+    for entry in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/roms")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("ch8") {
+            continue;
+        }
+        let rom = fs::read(&path).unwrap();
+        let entry = database
+            .lookup(&rom)
+            .unwrap_or_else(|| panic!("{} isn't in the database", path.display()));
+        assert!(entry.computer.is_some(), "{} has no platform", entry.title);
+    }
+}
+
+#[test]
+fn rom_settings() {
+    let json = r##"[{
+        "title": "Test",
+        "authors": ["Someone"],
+        "release": "2024",
+        "roms": {
+            "ABCDEF": {
+                "platforms": ["megachip8", "superchip"],
+                "quirkyPlatforms": {"superchip": {"shift": false, "vblank": true}},
+                "tickrate": 30,
+                "colors": {"pixels": ["#000000", "#FF0000"]},
+                "keys": {"left": 4, "right": 6},
+                "screenRotation": 0
+            },
+            "123456": {"platforms": ["originalChip8"], "startAddress": 1536}
+        }
+    }]"##;
+    let database = Database::parse(json).unwrap();
+    assert_eq!(database.len(), 2);
+
+    let entry = database.lookup_hash("abcdef").unwrap();
+    assert_eq!(entry.platform.as_deref(), Some("superchip"));
+    assert_eq!(entry.computer, Some(Computer::SuperChip));
+    assert_eq!(
+        entry.quirks,
+        Some(Quirks {
+            shift_uses_vy: true,
+            display_wait: true,
+            ..Quirks::SUPER_CHIP
+        })
+    );
+    assert_eq!(entry.tickrate, Some(30));
+    assert_eq!(entry.colors, vec![[0, 0, 0], [255, 0, 0]]);
+    assert_eq!(
+        entry.keys,
+        vec![("left".to_string(), 4), ("right".to_string(), 6)]
+    );
+
+    let eti = database.lookup_hash("123456").unwrap();
+    assert_eq!(eti.computer, Some(Computer::Eti));
+    assert_eq!(eti.quirks, Some(Quirks::COSMAC_VIP));
+
+    assert!(database.lookup_hash("000000").is_none());
+    assert_eq!(database::platform("chip8x"), None);
+}
@@ -0,0 +1,29 @@
+//! The interpreter and the disassembler agree on what is an instruction: every opcode the
+//! disassembler can't name is an invalid opcode to the interpreter, on every computer.
+
+use chippi::disasm;
+use chippi::{Chip8, Chip8Error, Computer, Quirks};
+
+#[test]
+fn only_named_opcodes_run() {
+    for computer in [
+        Computer::Normal,
+        Computer::Eti,
+        Computer::SuperChip,
+        Computer::XoChip,
+    ] {
+        let start = computer.program_start() as u16;
+        for opcode in 0..=u16::MAX {
+            // room after it for the address of a long load
+            let rom = [opcode.to_be_bytes(), [0; 2]].concat();
+            let mut chip = Chip8::from_bytes(computer, Quirks::MODERN, &rom).unwrap();
+            let invalid = chip.tick() == Err(Chip8Error::InvalidOpcode { pc: start, opcode });
+            let (text, _) = disasm::disassemble(&rom, 0, computer);
+            assert_eq!(
+                invalid,
+                text.starts_with("DW"),
+                "{opcode:04X} on {computer:?} is {text}"
+            );
+        }
+    }
+}
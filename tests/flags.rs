@@ -0,0 +1,76 @@
+//! How the 8xy_ instructions set VF: the flag is written after the result, so it's what's left
+//! when x is F, and SUB/SUBN report no borrow for equal operands. Octo's comparisons rely on both.
+
+use chippi::{Chip8, Computer, Quirks};
+
+// runs `program` from 0x200 one instruction at a time and returns the registers
+fn run(program: &[u16]) -> [u8; 16] {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &rom).unwrap();
+    for _ in program {
+        chip.tick().unwrap();
+    }
+    *chip.regs()
+}
+
+#[test]
+fn add_sets_the_carry() {
+    // V0 = 0xFF + 2
+    let regs = run(&[0x60FF, 0x6102, 0x8014]);
+    assert_eq!((regs[0], regs[0xF]), (0x01, 1));
+    // V0 = 0x10 + 2
+    let regs = run(&[0x6010, 0x6102, 0x8014]);
+    assert_eq!((regs[0], regs[0xF]), (0x12, 0));
+}
+
+#[test]
+fn sub_reports_no_borrow() {
+    // V0 = 5 - 3
+    let regs = run(&[0x6005, 0x6103, 0x8015]);
+    assert_eq!((regs[0], regs[0xF]), (2, 1));
+    // V0 = 3 - 5
+    let regs = run(&[0x6003, 0x6105, 0x8015]);
+    assert_eq!((regs[0], regs[0xF]), (0xFE, 0));
+    // equal operands don't borrow either
+    let regs = run(&[0x6004, 0x6104, 0x8015]);
+    assert_eq!((regs[0], regs[0xF]), (0, 1));
+}
+
+#[test]
+fn subn_reports_no_borrow() {
+    // V0 = 5 - 3
+    let regs = run(&[0x6003, 0x6105, 0x8017]);
+    assert_eq!((regs[0], regs[0xF]), (2, 1));
+    // V0 = 3 - 5
+    let regs = run(&[0x6005, 0x6103, 0x8017]);
+    assert_eq!((regs[0], regs[0xF]), (0xFE, 0));
+    let regs = run(&[0x6004, 0x6104, 0x8017]);
+    assert_eq!((regs[0], regs[0xF]), (0, 1));
+}
+
+#[test]
+fn shifts_set_the_bit_shifted_out() {
+    let regs = run(&[0x6005, 0x8006]);
+    assert_eq!((regs[0], regs[0xF]), (0x02, 1));
+    let regs = run(&[0x6081, 0x800E]);
+    assert_eq!((regs[0], regs[0xF]), (0x02, 1));
+    let regs = run(&[0x6040, 0x800E]);
+    assert_eq!((regs[0], regs[0xF]), (0x80, 0));
+}
+
+// with VF as Vx the result is written over by the flag
+#[test]
+fn the_flag_wins_over_the_result_in_vf() {
+    // VF = 0xFF + 2, carry
+    assert_eq!(run(&[0x6FFF, 0x6102, 0x8F14])[0xF], 1);
+    // VF = 1 + 2, no carry
+    assert_eq!(run(&[0x6F01, 0x6102, 0x8F14])[0xF], 0);
+    // VF = 5 - 3, no borrow
+    assert_eq!(run(&[0x6F05, 0x6103, 0x8F15])[0xF], 1);
+    // VF = 3 - 5, borrow
+    assert_eq!(run(&[0x6F05, 0x6103, 0x8F17])[0xF], 0);
+    // VF = 2 >> 1, a 0 shifted out
+    assert_eq!(run(&[0x6F02, 0x8F06])[0xF], 0);
+    // VF = 0x40 << 1, a 0 shifted out
+    assert_eq!(run(&[0x6F40, 0x8F0E])[0xF], 0);
+}
@@ -0,0 +1,54 @@
+//! The headless runner: the frames it reports, what counts towards an instruction limit, and the
+//! key scripts it accepts.
+
+use chippi::chip8::Chip8Error;
+use chippi::headless::{self, KeyScript, Limit};
+use chippi::{Chip8, Computer, Quirks};
+
+// runs `program` from 0x200 at 10 instructions a frame
+fn chip(program: &[u16]) -> Chip8 {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &rom).unwrap();
+    chip.set_instructions_per_second(600);
+    chip
+}
+
+#[test]
+fn errors_report_the_frames_run() {
+    // counts V0 up to 255 and then jumps into the font, where 0xF090 isn't an instruction
+    let mut chip = chip(&[0x7001, 0x3000, 0x1200, 0x1000]);
+    let result = headless::run(&mut chip, Limit::Frames(600), &KeyScript::default());
+    let Err((frames, Chip8Error::InvalidOpcode { .. })) = result else {
+        panic!("expected an invalid opcode, got {result:?}");
+    };
+    // 3 instructions a round, the 256th round hits the bad opcode
+    assert_eq!(frames, (3 * 255 + 3) / 10 + 1);
+}
+
+#[test]
+fn waiting_for_a_key_isnt_an_instruction() {
+    // V1 = key, then count in V0
+    let mut chip = chip(&[0xF10A, 0x7001, 0x1202]);
+    let keys = KeyScript::parse("5:7").unwrap();
+    assert_eq!(
+        headless::run(&mut chip, Limit::Instructions(21), &keys),
+        Ok(8)
+    );
+    assert_eq!(chip.regs()[1], 7);
+    assert_eq!(chip.regs()[0], 10);
+}
+
+#[test]
+fn a_key_that_never_comes_ends_the_run() {
+    let mut chip = chip(&[0xF10A]);
+    let result = headless::run(&mut chip, Limit::Instructions(100), &KeyScript::default());
+    assert_eq!(result, Ok(1));
+}
+
+#[test]
+fn key_ranges_go_forwards() {
+    assert!(KeyScript::parse("10-30:5").is_ok());
+    assert!(KeyScript::parse("10-10:5").is_ok());
+    let error = KeyScript::parse("30-10:5").unwrap_err();
+    assert!(error.contains("30-10:5"), "{error}");
+}
@@ -0,0 +1,122 @@
+//! Movies: the header and frames survive being written and read back, and a recording replays the
+//! machine it was recorded from exactly.
+
+use std::fs;
+
+use chippi::movie::{self, Movie, MovieHeader, MOVIE_VERSION};
+use chippi::{Chip8, Chip8Error, Computer, Quirks};
+
+const BLINKY: &str = "roms/Blinky [Hans Christian Egeberg, 1991].ch8";
+
+fn header() -> MovieHeader {
+    MovieHeader {
+        rom_hash: movie::rom_hash(b"rom"),
+        computer: Computer::SuperChip,
+        quirks: Quirks::SUPER_CHIP,
+        ips: 1500,
+        seed: 0x0123_4567_89AB_CDEF,
+    }
+}
+
+#[test]
+fn movies_read_back() {
+    let frames = vec![0, 1, 0x8000, 0xFFFF, 0x0120];
+    let data = Movie {
+        header: header(),
+        frames: frames.clone(),
+    }
+    .to_bytes();
+    assert_eq!(data.len(), 40 + 2 * frames.len());
+
+    let movie = Movie::parse(&data).unwrap();
+    assert_eq!(movie.header, header());
+    assert_eq!(movie.frames, frames);
+
+    // a frame cut off while it was written is left out
+    let movie = Movie::parse(&[data.as_slice(), &[0x12]].concat()).unwrap();
+    assert_eq!(movie.frames, frames);
+    let movie = Movie::parse(&header().to_bytes()).unwrap();
+    assert!(movie.frames.is_empty());
+}
+
+#[test]
+fn bad_movies_are_rejected() {
+    let data = header().to_bytes();
+    let patched = |at: usize, bytes: &[u8]| {
+        let mut data = data.clone();
+        data[at..at + bytes.len()].copy_from_slice(bytes);
+        data
+    };
+    let cases = [
+        (data[..39].to_vec(), "not a chippi movie"),
+        (patched(0, b"CHPS"), "not a chippi movie"),
+        (
+            patched(4, &(MOVIE_VERSION + 1).to_le_bytes()),
+            "movie version not supported",
+        ),
+        (patched(26, &[9]), "unknown computer"),
+        (
+            patched(28, &0u32.to_le_bytes()),
+            "instructions per second out of range",
+        ),
+        (
+            patched(28, &u32::MAX.to_le_bytes()),
+            "instructions per second out of range",
+        ),
+    ];
+    for (data, reason) in cases {
+        let error = Movie::parse(&data).err();
+        assert_eq!(error, Some(Chip8Error::InvalidMovie(reason)));
+    }
+}
+
+#[test]
+fn keys_pack_into_bits() {
+    let mut kb = [0; 16];
+    assert_eq!(movie::keys_to_bits(&kb), 0);
+    kb[0] = 1;
+    kb[5] = 1;
+    kb[0xF] = 1;
+    assert_eq!(movie::keys_to_bits(&kb), 0b1000_0000_0010_0001);
+    assert_eq!(movie::bits_to_keys(0b1000_0000_0010_0001), kb);
+    for bits in [0, 1, 0x5A5A, 0xFFFF] {
+        assert_eq!(movie::keys_to_bits(&movie::bits_to_keys(bits)), bits);
+    }
+}
+
+#[test]
+fn recordings_replay_exactly() {
+    let rom = fs::read(BLINKY).unwrap();
+    let header = MovieHeader {
+        rom_hash: movie::rom_hash(&rom),
+        computer: Computer::Normal,
+        quirks: Quirks::CHIP48,
+        ips: 900,
+        seed: 42,
+    };
+    let start = |header: &MovieHeader| {
+        let mut chip = Chip8::from_bytes(header.computer, header.quirks, &rom).unwrap();
+        chip.set_seed(header.seed);
+        chip.set_instructions_per_second(header.ips);
+        chip
+    };
+
+    // steer blinky around for ten seconds, recording as it goes
+    let mut chip = start(&header);
+    let mut frames = Vec::new();
+    for frame in 0..600 {
+        chip.kb = [0; 16];
+        chip.kb[[3, 6, 7, 8][frame / 40 % 4]] = 1;
+        frames.push(movie::keys_to_bits(&chip.kb));
+        chip.run_frame().unwrap();
+    }
+
+    let movie = Movie::parse(&Movie { header, frames }.to_bytes()).unwrap();
+    let mut replay = start(&movie.header);
+    for keys in &movie.frames {
+        replay.kb = movie::bits_to_keys(*keys);
+        replay.run_frame().unwrap();
+    }
+    assert_eq!(replay.regs(), chip.regs());
+    assert_eq!(replay.save_state(), chip.save_state());
+}
@@ -0,0 +1,343 @@
+//! Runs test ROMs headlessly and compares the display they end on with the golden framebuffers
+//! in `tests/golden`. A ROM with a `.checks` file next to it has the checks whose part of the
+//! screen came out wrong reported by name.
+//!
+//! `CHIPPI_BLESS=1 cargo test --test roms` writes the golden framebuffers from the current output
+//! instead. Look at them before committing: they are only as right as the emulator that drew them.
+//!
+//! opcodes.8o and quirks.8o are the regression suite. Timendus' chip8-test-suite isn't in the repo
+//! and has no goldens, so its tests are ignored and only there to try by hand. Put its ROMs in
+//! `tests/roms/timendus` as the README there says and run them with `cargo test -- --ignored`.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chippi::chip8::{DEFAULT_INSTRUCTIONS_PER_SECOND, TIMER_FREQUENCY};
+use chippi::database::Database;
+use chippi::headless::{self, KeyScript, Limit};
+use chippi::{octo, Chip8, Computer, Quirks, DEFAULT_SEED};
+
+// where the timendus roms read the menu choice from, so they don't wait for a key press
+const MENU_CHOICE_LOCATION: usize = 0x1FF;
+
+struct TestRom {
+    // names the golden framebuffer
+    name: &'static str,
+    // relative to the crate root
+    path: &'static str,
+    computer: Computer,
+    quirks: Quirks,
+    ips: u32,
+    frames: u64,
+    keys: &'static str,
+    menu_choice: Option<u8>,
+}
+
+impl TestRom {
+    const fn new(name: &'static str, path: &'static str) -> TestRom {
+        TestRom {
+            name,
+            path,
+            computer: Computer::Normal,
+            quirks: Quirks::CHIP48,
+            ips: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            frames: 120,
+            keys: "",
+            menu_choice: None,
+        }
+    }
+
+    // one of the bundled roms, set up the way the emulator runs it: what the rom database knows
+    // about it, or failing that what the rom looks like
+    fn bundled(name: &'static str, path: &'static str) -> TestRom {
+        let rom =
+            fs::read(root().join(path)).unwrap_or_else(|e| panic!("Couldn't read {path}: {e}"));
+        let entry = Database::bundled().lookup(&rom);
+        let computer = entry
+            .and_then(|entry| entry.computer)
+            .unwrap_or_else(|| Computer::detect(path, &rom));
+        TestRom {
+            computer,
+            quirks: entry
+                .and_then(|entry| entry.quirks)
+                .unwrap_or_else(|| computer.default_quirks()),
+            ips: entry
+                .and_then(|entry| entry.tickrate)
+                .map_or(DEFAULT_INSTRUCTIONS_PER_SECOND, |tickrate| {
+                    tickrate * TIMER_FREQUENCY
+                }),
+            ..TestRom::new(name, path)
+        }
+    }
+
+    const fn quirks(mut self, quirks: Quirks) -> TestRom {
+        self.quirks = quirks;
+        self
+    }
+
+    const fn computer(mut self, computer: Computer) -> TestRom {
+        self.computer = computer;
+        self
+    }
+
+    const fn frames(mut self, frames: u64) -> TestRom {
+        self.frames = frames;
+        self
+    }
+
+    const fn keys(mut self, keys: &'static str) -> TestRom {
+        self.keys = keys;
+        self
+    }
+
+    const fn menu_choice(mut self, choice: u8) -> TestRom {
+        self.menu_choice = Some(choice);
+        self
+    }
+}
+
+// a named region of the screen one check draws its result in
+struct Check {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    name: String,
+}
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn load_rom(path: &Path, computer: Computer) -> Vec<u8> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("8o") => octo::compile_file(path, computer).unwrap_or_else(|e| panic!("{e}")),
+        _ => fs::read(path).unwrap_or_else(|e| panic!("Couldn't read {}: {e}", path.display())),
+    }
+}
+
+fn load_checks(path: &Path) -> Vec<Check> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut checks = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(5, ' ');
+        let mut number = || {
+            fields
+                .next()
+                .and_then(|f| f.parse().ok())
+                .unwrap_or_else(|| {
+                    panic!(
+                        "{}:{}: expected x y width height name",
+                        path.display(),
+                        n + 1
+                    )
+                })
+        };
+        let (x, y, width, height) = (number(), number(), number(), number());
+        let name = fields.next().unwrap_or_default().to_string();
+        checks.push(Check {
+            x,
+            y,
+            width,
+            height,
+            name,
+        });
+    }
+    checks
+}
+
+// the rows of an ascii framebuffer, as bytes so pixels can be indexed
+fn rows(display: &str) -> Vec<&[u8]> {
+    display.lines().map(str::as_bytes).collect()
+}
+
+fn failed_checks<'a>(checks: &'a [Check], expected: &str, actual: &str) -> Vec<&'a str> {
+    let (expected, actual) = (rows(expected), rows(actual));
+    let pixel =
+        |rows: &[&[u8]], x: usize, y: usize| rows.get(y).and_then(|row| row.get(x)).copied();
+    checks
+        .iter()
+        .filter(|check| {
+            (check.y..check.y + check.height).any(|y| {
+                (check.x..check.x + check.width)
+                    .any(|x| pixel(&expected, x, y) != pixel(&actual, x, y))
+            })
+        })
+        .map(|check| check.name.as_str())
+        .collect()
+}
+
+fn run(test: TestRom) {
+    let path = root().join(test.path);
+    if !path.exists() {
+        panic!(
+            "{} not found, see tests/roms/timendus/README.md for the ones that aren't in the repo",
+            path.display()
+        );
+    }
+
+    let rom = load_rom(&path, test.computer);
+    let mut chip = Chip8::from_bytes(test.computer, test.quirks, &rom)
+        .unwrap_or_else(|e| panic!("Couldn't load {}: {e}", test.path));
+    chip.set_seed(DEFAULT_SEED);
+    chip.set_instructions_per_second(test.ips);
+    if let Some(choice) = test.menu_choice {
+        chip.ram[MENU_CHOICE_LOCATION] = choice;
+    }
+    let keys = KeyScript::parse(test.keys).unwrap();
+    if let Err((_, e)) = headless::run(&mut chip, Limit::Frames(test.frames), &keys) {
+        panic!(
+            "{} stopped with an error: {e}\n{}",
+            test.name,
+            headless::display_to_ascii(&chip)
+        );
+    }
+    let actual = headless::display_to_ascii(&chip);
+
+    let golden_path = root()
+        .join("tests/golden")
+        .join(format!("{}.txt", test.name));
+    if env::var_os("CHIPPI_BLESS").is_some() {
+        fs::write(&golden_path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "{} has no golden framebuffer at {}. It drew:\n{actual}\nRun with CHIPPI_BLESS=1 to keep it",
+            test.name,
+            golden_path.display()
+        )
+    });
+    if expected == actual {
+        return;
+    }
+
+    let checks = load_checks(&path.with_extension("checks"));
+    let failed = failed_checks(&checks, &expected, &actual);
+    let summary = match failed.len() {
+        0 => format!("{} doesn't match its golden framebuffer", test.name),
+        n => format!(
+            "{}: {n} of {} checks failed:\n  {}",
+            test.name,
+            checks.len(),
+            failed.join("\n  ")
+        ),
+    };
+    panic!("{summary}\nexpected:\n{expected}\ngot:\n{actual}");
+}
+
+#[test]
+fn opcodes() {
+    run(TestRom::new("opcodes", "tests/roms/opcodes.8o"));
+}
+
+// loaded at 0x600, every check should come out the same
+#[test]
+fn opcodes_eti_660() {
+    run(TestRom::new("opcodes", "tests/roms/opcodes.8o").computer(Computer::Eti));
+}
+
+#[test]
+fn quirks_cosmac_vip() {
+    run(TestRom::new("quirks-vip", "tests/roms/quirks.8o").quirks(Quirks::COSMAC_VIP));
+}
+
+#[test]
+fn quirks_chip48() {
+    run(TestRom::new("quirks-chip48", "tests/roms/quirks.8o").quirks(Quirks::CHIP48));
+}
+
+#[test]
+fn quirks_super_chip() {
+    run(TestRom::new("quirks-schip", "tests/roms/quirks.8o").quirks(Quirks::SUPER_CHIP));
+}
+
+#[test]
+fn quirks_modern() {
+    run(TestRom::new("quirks-modern", "tests/roms/quirks.8o").quirks(Quirks::MODERN));
+}
+
+// the bundled roms, after a few seconds of attract mode. the chippi logo blinks, a second in it's
+// showing, and blinky takes a while to draw its maze
+#[test]
+fn bundled_roms() {
+    run(TestRom::bundled("chippi", "roms/chippi.ch8").frames(60));
+    run(TestRom::bundled("pong", "roms/Pong (1 player).ch8").frames(300));
+    run(TestRom::bundled(
+        "breakout",
+        "roms/Breakout (Brix hack) [David Winter, 1997].ch8",
+    )
+    .frames(300));
+    run(TestRom::bundled("blinky", "roms/Blinky [Hans Christian Egeberg, 1991].ch8").frames(1200));
+}
+
+#[test]
+#[ignore = "needs Timendus ROMs"]
+fn timendus_logos() {
+    run(TestRom::new(
+        "timendus-chip8-logo",
+        "tests/roms/timendus/1-chip8-logo.ch8",
+    ));
+    run(TestRom::new(
+        "timendus-ibm-logo",
+        "tests/roms/timendus/2-ibm-logo.ch8",
+    ));
+}
+
+#[test]
+#[ignore = "needs Timendus ROMs"]
+fn timendus_corax_plus() {
+    run(TestRom::new(
+        "timendus-corax+",
+        "tests/roms/timendus/3-corax+.ch8",
+    ));
+}
+
+#[test]
+#[ignore = "needs Timendus ROMs"]
+fn timendus_flags() {
+    run(TestRom::new(
+        "timendus-flags",
+        "tests/roms/timendus/4-flags.ch8",
+    ));
+}
+
+#[test]
+#[ignore = "needs Timendus ROMs"]
+fn timendus_quirks() {
+    let path = "tests/roms/timendus/5-quirks.ch8";
+    // the display wait quirk needs a few seconds to measure
+    run(TestRom::new("timendus-quirks-chip8", path)
+        .quirks(Quirks::COSMAC_VIP)
+        .menu_choice(1)
+        .frames(600));
+    run(TestRom::new("timendus-quirks-schip", path)
+        .computer(Computer::SuperChip)
+        .quirks(Quirks::SUPER_CHIP)
+        .menu_choice(2)
+        .frames(600));
+    run(TestRom::new("timendus-quirks-xochip", path)
+        .computer(Computer::XoChip)
+        .quirks(Quirks::MODERN)
+        .menu_choice(3)
+        .frames(600));
+}
+
+#[test]
+#[ignore = "needs Timendus ROMs"]
+fn timendus_keypad() {
+    // Fx0A. chippi takes the key as soon as it's pressed rather than waiting for it to be let go
+    // like the COSMAC VIP, so the ROM reports it as not released
+    run(
+        TestRom::new("timendus-keypad-getkey", "tests/roms/timendus/6-keypad.ch8")
+            .menu_choice(3)
+            .keys("30-40:5"),
+    );
+}
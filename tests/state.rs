@@ -0,0 +1,110 @@
+//! Save states: a loaded state carries on exactly like the machine it was saved from, and a state
+//! that can't be loaded leaves the machine as it was.
+
+use std::fs;
+
+use chippi::chip8::INSTRUCTIONS_PER_SECOND_RANGE;
+use chippi::state::STATE_VERSION;
+use chippi::{Chip8, Chip8Error, Computer, Quirks};
+
+// blinky draws, reads keys and uses RND, so most of the machine is in use after a second
+fn blinky() -> Chip8 {
+    let rom = fs::read("roms/Blinky [Hans Christian Egeberg, 1991].ch8").unwrap();
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &rom).unwrap();
+    chip.set_seed(7);
+    for _ in 0..60 {
+        chip.run_frame().unwrap();
+    }
+    chip
+}
+
+fn run_frames(chip: &mut Chip8, frames: usize) {
+    for frame in 0..frames {
+        chip.kb = [(frame % 7 == 0) as u8; 16];
+        chip.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn states_carry_on_where_they_left_off() {
+    let mut chip = blinky();
+    let state = chip.save_state();
+    run_frames(&mut chip, 120);
+    let expected = chip.save_state();
+
+    // into a fresh machine, and into the one it came from
+    let mut other = Chip8::from_bytes(Computer::XoChip, Quirks::MODERN, &[0x12, 0x00]).unwrap();
+    other.load_state(&state).unwrap();
+    assert_eq!(other.save_state(), state);
+    run_frames(&mut other, 120);
+    assert_eq!(other.save_state(), expected);
+
+    chip.load_state(&state).unwrap();
+    run_frames(&mut chip, 120);
+    assert_eq!(chip.save_state(), expected);
+}
+
+#[test]
+fn bad_states_leave_the_machine_alone() {
+    let state = blinky().save_state();
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::COSMAC_VIP, &[0x12, 0x00]).unwrap();
+    let before = chip.save_state();
+    let end = state.len();
+    let patched = |at: usize, bytes: &[u8]| {
+        let mut state = state.clone();
+        state[at..at + bytes.len()].copy_from_slice(bytes);
+        state
+    };
+
+    let cases = [
+        (state[..end - 1].to_vec(), "save state is truncated"),
+        (state[..3].to_vec(), "save state is truncated"),
+        (
+            [state.as_slice(), &[0]].concat(),
+            "save state has trailing data",
+        ),
+        (patched(0, b"CHPX"), "not a chippi save state"),
+        (
+            patched(4, &(STATE_VERSION + 1).to_le_bytes()),
+            "save state version not supported",
+        ),
+        (patched(6, &[9]), "unknown computer"),
+        // the ram size follows the magic, the version, the computer and the quirks
+        (
+            patched(8, &8192u32.to_le_bytes()),
+            "memory size doesn't match the computer",
+        ),
+        // the state ends with the instructions per second, the cycle remainder and the rng
+        (patched(end - 16, &0u32.to_le_bytes()), "speed out of range"),
+        (
+            patched(end - 16, &u32::MAX.to_le_bytes()),
+            "speed out of range",
+        ),
+        (
+            patched(end - 12, &60u32.to_le_bytes()),
+            "speed out of range",
+        ),
+    ];
+    for (data, reason) in cases {
+        assert_eq!(
+            chip.load_state(&data),
+            Err(Chip8Error::InvalidState(reason))
+        );
+        assert!(chip.save_state() == before, "{reason} changed the machine");
+    }
+}
+
+#[test]
+fn the_speed_stays_in_range() {
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &[0x12, 0x00]).unwrap();
+    chip.set_instructions_per_second(u32::MAX);
+    assert_eq!(
+        chip.instructions_per_second(),
+        *INSTRUCTIONS_PER_SECOND_RANGE.end()
+    );
+    chip.set_instructions_per_second(0);
+    assert_eq!(
+        chip.instructions_per_second(),
+        *INSTRUCTIONS_PER_SECOND_RANGE.start()
+    );
+}
@@ -0,0 +1,129 @@
+//! The Super-Chip instructions: the high resolution mode, scrolling, 16x16 sprites, the big font,
+//! the RPL flags and EXIT.
+
+use chippi::{Chip8, Chip8Error, Computer, Quirks, StepOutcome};
+
+// runs `program` (instructions, then any data) from 0x200, one tick per instruction for `steps`
+fn run(computer: Computer, program: &[u16], steps: usize) -> Chip8 {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = Chip8::from_bytes(computer, Quirks::SUPER_CHIP, &rom).unwrap();
+    for _ in 0..steps {
+        chip.tick().unwrap();
+    }
+    chip
+}
+
+// the lit pixels, as (x, y)
+fn lit(chip: &Chip8) -> Vec<(usize, usize)> {
+    let width = chip.display_width();
+    (0..width * chip.display_height())
+        .filter(|i| chip.display[*i] != 0)
+        .map(|i| (i % width, i / width))
+        .collect()
+}
+
+#[test]
+fn high_resolution() {
+    // HIGH, a row of 8 at (120, 60), then LOW. the row is at 0x20C
+    let program = [0x00FF, 0xA20C, 0x6078, 0x613C, 0xD011, 0x00FE, 0xFF00];
+    let chip = run(Computer::SuperChip, &program, 5);
+    assert_eq!((chip.display_width(), chip.display_height()), (128, 64));
+    assert_eq!(lit(&chip), (120..128).map(|x| (x, 60)).collect::<Vec<_>>());
+
+    let chip = run(Computer::SuperChip, &program, 6);
+    assert_eq!((chip.display_width(), chip.display_height()), (64, 32));
+    assert_eq!(lit(&chip), vec![]);
+}
+
+#[test]
+fn large_sprites() {
+    // a 16x16 box outline at (4, 2), drawn twice. the sprite is at 0x20A
+    let mut program = vec![0x00FF, 0xA20A, 0x6004, 0x6102, 0xD010];
+    program.push(0xFFFF);
+    program.extend([0x8001; 14]);
+    program.push(0xFFFF);
+    let chip = run(Computer::SuperChip, &program, 5);
+    let pixels = lit(&chip);
+    assert_eq!(pixels.len(), 16 + 16 + 14 * 2);
+    for (x, y) in [(4, 2), (19, 2), (4, 17), (19, 17), (4, 9), (19, 9)] {
+        assert!(pixels.contains(&(x, y)), "({x}, {y}) isn't lit");
+    }
+    assert!(!pixels.contains(&(5, 3)));
+    assert_eq!(chip.regs()[0xF], 0);
+
+    // over itself, which erases it and collides
+    program.insert(5, 0xD010);
+    let chip = run(Computer::SuperChip, &program, 6);
+    assert_eq!(lit(&chip), vec![]);
+    assert_eq!(chip.regs()[0xF], 1);
+
+    // the plain CHIP-8 draws no rows for n = 0
+    let chip = run(Computer::Normal, &program[1..], 4);
+    assert_eq!(lit(&chip), vec![]);
+}
+
+#[test]
+fn scrolling() {
+    // a pixel at (10, 10) in high resolution, scrolled down 3, right 4, then left 4 until it
+    // falls off the edge. the sprite is at 0x216
+    let program = [
+        0x00FF, 0xA216, 0x600A, 0x610A, 0xD011, 0x00C3, 0x00FB, 0x00FC, 0x00FC, 0x00FC, 0x00FC,
+        0x8000,
+    ];
+    let mut chip = run(Computer::SuperChip, &program, 5);
+    assert_eq!(lit(&chip), vec![(10, 10)]);
+    for expected in [
+        vec![(10, 13)],
+        vec![(14, 13)],
+        vec![(10, 13)],
+        vec![(6, 13)],
+        vec![(2, 13)],
+    ] {
+        chip.tick().unwrap();
+        assert_eq!(lit(&chip), expected);
+    }
+    chip.tick().unwrap();
+    assert_eq!(lit(&chip), vec![]);
+}
+
+#[test]
+fn big_font() {
+    // I = the 8x10 sprite for 7
+    let chip = run(Computer::SuperChip, &[0x6007, 0xF030], 2);
+    let i = chip.ireg() as usize;
+    assert_eq!(i, 0x50 + 7 * 10);
+    assert!(chip.ram[i..i + 10].iter().any(|row| *row != 0));
+
+    let rom: Vec<u8> = [0x6007u16, 0xF030]
+        .iter()
+        .flat_map(|op| op.to_be_bytes())
+        .collect();
+    let mut chip = Chip8::from_bytes(Computer::Normal, Quirks::CHIP48, &rom).unwrap();
+    chip.tick().unwrap();
+    assert_eq!(
+        chip.tick(),
+        Err(Chip8Error::InvalidOpcode {
+            pc: 0x202,
+            opcode: 0xF030
+        })
+    );
+}
+
+#[test]
+fn rpl_flags() {
+    // V0-V3 = 1-4 into the flags, zeroed, and V0-V2 back out again
+    let program = [
+        0x6001, 0x6102, 0x6203, 0x6304, 0xF375, 0x6000, 0x6100, 0x6200, 0x6300, 0xF285,
+    ];
+    let chip = run(Computer::SuperChip, &program, program.len());
+    assert_eq!(chip.regs()[..4], [1, 2, 3, 0]);
+}
+
+#[test]
+fn exit() {
+    let mut chip = run(Computer::SuperChip, &[0x00FD, 0x6001], 0);
+    assert_eq!(chip.tick(), Ok(StepOutcome::Exited));
+    assert!(chip.has_exited());
+    assert_eq!(chip.tick(), Ok(StepOutcome::Exited));
+    assert_eq!(chip.regs()[0], 0);
+}
@@ -0,0 +1,97 @@
+//! The XO-Chip instructions: the 64K of memory and the long load of I, saving and loading
+//! register ranges, the bitplanes and the audio pattern and pitch.
+
+use chippi::{Chip8, Chip8Error, Computer, Quirks};
+
+// runs `program` (instructions, then any data) from 0x200, one tick per instruction for `steps`
+fn run(program: &[u16], steps: usize) -> Chip8 {
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = Chip8::from_bytes(Computer::XoChip, Quirks::MODERN, &rom).unwrap();
+    for _ in 0..steps {
+        chip.tick().unwrap();
+    }
+    chip
+}
+
+// the lit pixels, as (x, y) and the planes they are lit on
+fn lit(chip: &Chip8) -> Vec<((usize, usize), u8)> {
+    let width = chip.display_width();
+    (0..width * chip.display_height())
+        .filter(|i| chip.display[*i] != 0)
+        .map(|i| ((i % width, i / width), chip.display[i]))
+        .collect()
+}
+
+#[test]
+fn long_memory() {
+    // I = 0x2000 and V0 stored there, past the 4K of the other computers
+    let chip = run(&[0xF000, 0x2000, 0x6007, 0xF055], 3);
+    assert_eq!(chip.ram.len(), 0x10000);
+    assert_eq!(chip.ram[0x2000], 7);
+    assert_eq!(chip.ireg(), 0x2001);
+
+    let chip = run(&[0xF000, 0x1234], 1);
+    assert_eq!(chip.ireg(), 0x1234);
+    assert_eq!(chip.pc(), 0x204);
+}
+
+#[test]
+fn skips_step_over_long_loads() {
+    // SE V0, 0 skips all four bytes of the F000 and lands on LD V1, 1
+    let chip = run(&[0x3000, 0xF000, 0x1234, 0x6101], 2);
+    assert_eq!(chip.ireg(), 0);
+    assert_eq!(chip.regs()[1], 1);
+    assert_eq!(chip.pc(), 0x208);
+}
+
+#[test]
+fn register_ranges() {
+    // V1-V3 = 1-3, saved at 0x300, saved backwards at 0x310 and loaded back from there
+    let program = [
+        0x6101, 0x6202, 0x6303, 0xA300, 0x5132, 0xA310, 0x5312, 0x5133,
+    ];
+    let chip = run(&program, program.len());
+    assert_eq!(chip.ram[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(chip.ram[0x310..0x314], [3, 2, 1, 0]);
+    assert_eq!(chip.regs()[..5], [0, 3, 2, 1, 0]);
+    assert_eq!(chip.ireg(), 0x310);
+
+    let rom: Vec<u8> = [0x5132u16].iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut chip = Chip8::from_bytes(Computer::SuperChip, Quirks::SUPER_CHIP, &rom).unwrap();
+    assert_eq!(
+        chip.tick(),
+        Err(Chip8Error::InvalidOpcode {
+            pc: 0x200,
+            opcode: 0x5132
+        })
+    );
+}
+
+#[test]
+fn bitplanes() {
+    // a pixel at (0, 0) on plane 2, one at (0, 8) on both planes, plane 2 scrolled up 4 and plane 1
+    // cleared. the sprites, one row for each plane, are at 0x214
+    let program = [
+        0xA214, 0xF201, 0xD001, 0xF301, 0x6108, 0xD011, 0xF201, 0x00D4, 0xF101, 0x00E0, 0x8080,
+    ];
+    let chip = run(&program, 6);
+    assert_eq!(lit(&chip), vec![((0, 0), 2), ((0, 8), 3)]);
+    assert_eq!(chip.regs()[0xF], 0);
+
+    let chip = run(&program, 8);
+    assert_eq!(lit(&chip), vec![((0, 4), 2), ((0, 8), 1)]);
+
+    let chip = run(&program, 10);
+    assert_eq!(lit(&chip), vec![((0, 4), 2)]);
+}
+
+#[test]
+fn audio() {
+    // the pattern at 0x208 and a pitch of 0x80
+    let mut program = vec![0xA208, 0xF002, 0x6380, 0xF33A];
+    program.extend((0..8).map(|i| (2 * i + 1) << 8 | (2 * i + 2)));
+    let chip = run(&program, 4);
+    let pattern: Vec<u8> = (1..=16).collect();
+    assert_eq!(chip.audio_pattern()[..], pattern);
+    assert_eq!(chip.pitch(), 0x80);
+}